@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+// Feed arbitrary bytes through the assembler. Any input must either assemble or be rejected with an
+// AssemblerError; a panic is a bug.
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data);
+    let _ = hack_assembler::assemble(&source);
+});
@@ -0,0 +1,34 @@
+/* Large synthetic programs for benchmarks and stress tests, only built with the "bench" feature. A generated program
+    always assembles, so timing it measures the assembler's ordinary path rather than its error handling.
+*/
+use crate::ROM_SIZE;
+
+// The comps the generated C-instructions cycle through, with and without a dest and a jump.
+const C_INSTRUCTIONS: [&str; 6] = ["D=M", "M=D+1", "D=D-A", "AM=M-1", "D;JGT", "0;JMP"];
+
+// How many distinct variables are used, kept well below SCREEN so they never run into the memory map.
+const VARIABLES: usize = 64;
+
+/// Make a valid program of exactly `instructions` instructions, the same every time for the same number. It is
+/// split into blocks, each with a label and a mix of C-instructions, numeric A-instructions, variables, and jumps
+/// back to earlier labels. Panics if `instructions` is more than fits in the ROM.
+pub fn generate_program(instructions: usize) -> String {
+    assert!(instructions <= ROM_SIZE, "a program of {} instructions is too big for the ROM", instructions);
+    let mut program = String::with_capacity(instructions * 12);
+    let mut labels = 0;
+    for index in 0..instructions {
+        let line = match index % 8 {
+            0 => {
+                labels += 1;
+                format!("(BLOCK{})\n@{}", labels - 1, index % 1000)
+            }
+            2 => format!("@var{}", index % VARIABLES),
+            // Every label generated so far is already defined, so any of them is a valid target.
+            6 => format!("@BLOCK{}", (index / 8) % labels),
+            _ => C_INSTRUCTIONS[index % C_INSTRUCTIONS.len()].to_string(),
+        };
+        program.push_str(&line);
+        program.push('\n');
+    }
+    program
+}
@@ -0,0 +1,1996 @@
+/* A minimal assembler for the Hack computer from Nand2Tetris.
+    Takes the contents of a file in the symbolic Hack machine language and returns the corrosponding binary.
+    Implementation details and course:
+    https://www.coursera.org/learn/build-a-computer?s
+    https://www.nand2tetris.org/project06
+*/
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    io::Write,
+};
+
+#[cfg(feature = "bench")]
+mod generate;
+mod lint;
+mod self_test;
+mod warnings;
+#[cfg(feature = "bench")]
+pub use generate::generate_program;
+pub use lint::lint;
+pub use self_test::{check_fixture, check_fixture_dir, self_test};
+pub use warnings::{Warning, WarningKind, WarningLevel, Warnings};
+
+/// The canonical Hack no-op, `0`: compute zero, store it nowhere and don't jump (`1110101010000000`).
+pub const NOP: u16 = 0b1110_1010_1000_0000;
+
+/// Settings that change how a program is assembled. `Options::default()` is the standard assembler.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Symbols with fixed addresses, seeded into the table before the first pass instead of being allocated.
+    pub seeded_symbols: Vec<(String, u16)>,
+    /// Symbols with fixed values that are just numbers, eg a width, rather than RAM the program uses. Unlike seeded
+    /// symbols, variables may still be allocated at their values. One of the same name as a seeded symbol replaces it.
+    pub constants: Vec<(String, u16)>,
+    /// The highest RAM address a variable may be allocated; going past it is an error.
+    pub max_var_addr: Option<u16>,
+    /// Accept a line holding only a jump mnemonic, eg `JMP`, as shorthand for `0;JMP`.
+    pub jmp_shorthand: bool,
+    /// Scope symbols starting with `.` to the label before them, so `(.loop)` after `(MAIN)` is `MAIN.loop`.
+    pub local_labels: bool,
+    /// Start from an empty symbol table, without R0-R15, SP, LCL, ARG, THIS, THAT, SCREEN or KBD. Variables are
+    /// still allocated from 16.
+    pub no_predefined: bool,
+    /// Make a symbol that is not a label, predefined, or seeded an error instead of allocating it as a variable.
+    pub require_decl: bool,
+    /// Skip the symbol passes for a program whose A-instructions are all numbers, and which has no labels. Any symbol
+    /// is an error; even the predefined ones aren't known.
+    pub assume_resolved: bool,
+    /// The ROM address the program is loaded at. Labels, and the addresses in listings, are offset by it; numeric
+    /// A-instructions are not.
+    pub base_rom: u16,
+    /// The three bits that start every C-instruction, in place of `111`, for CPUs with a different opcode framing.
+    /// Only the low three bits are used.
+    pub c_prefix: Option<u8>,
+    /// Reject labels starting with `RESERVED_PREFIX`, so they can't clash with names the assembler generates.
+    pub strict: bool,
+    /// The longest an instruction may be, in characters once its whitespace is removed. A longer one is malformed,
+    /// which bounds the work done on a pathological input. None is no limit.
+    pub max_line_len: Option<usize>,
+    /// Allow A-instructions like `@SCREEN+32` that add and subtract numbers, labels and predefined or seeded symbols,
+    /// left to right. Variables can't be used, as their addresses depend on the order they are first seen.
+    pub expr: bool,
+    /// Warn about symbols named like a register or mnemonic, eg `@D` or `(JMP)`. They are ordinary symbols, but easy
+    /// to misread as part of a C-instruction.
+    pub warn_confusable: bool,
+    /// Require labels to start in the first column and instructions to be indented. Directives may be either.
+    pub column_strict: bool,
+    /// Check that as many words were emitted as the program has instructions, failing with `CountMismatch` if not.
+    /// A mismatch can only come from a bug in the assembler; debug builds always check.
+    pub verify_count: bool,
+    /// Reject a line with anything left after its instruction, eg `@i j`, which removing the whitespace would
+    /// otherwise quietly turn into `@ij`, or `(LOOP) x`, whose `x` would be dropped. Spacing around operators, as in
+    /// `D = M + 1`, is still allowed.
+    pub strict_eol: bool,
+}
+
+/// The start of the names the assembler keeps for symbols it generates itself. Labels may not use it under
+/// `Options::strict`.
+pub const RESERVED_PREFIX: &str = "__";
+
+#[allow(clippy::enum_variant_names)] // The names follow the course's A/C/L command terminology.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum CommandKind {
+    ACommand,
+    CCommand,
+    LCommand,
+    ICommand(IgnoreReason), // A line that is not an instruction, and why. Ignored completely.
+    MCommand,               // A line that failed to parse. It still takes up a ROM address; see ParsedLine::error.
+    OCommand(usize),        // A .org directive, and how many no-ops fill the ROM up to its address.
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+enum IgnoreReason {
+    Blank,   // Empty or whitespace only.
+    Comment, // Nothing but a // comment.
+}
+/// One parsed line of a program. Outside the assembler it is only seen through `for_each_instruction`, as an A- or
+/// C-instruction.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct ParsedLine {
+    // The "parts" of an instruction. CommandKind is either A, C, or L(abel).
+    // Depending on the CommandKind, Dest, Comp, and Jump are either Some(value) or None
+    // Examples:
+    // @100
+    // command_type: A_Command, symbol: Some("100"), dest: None, Comp: None, Jump: None
+    // D = M + 1;JEQ
+    // command_type: C_Command, symbol: None, dest: Some("D"), comp: Some("M + 1"), jump: Some("JEQ")
+    // (ORANGE)
+    // comamand_type: L_Command, symbol: Some("ORANGE"), ... : None
+    // source_line is the 1-based line of the input file the command came from, used for error reporting.
+    // error is why an MCommand couldn't be parsed, and None for every other kind.
+    command_type: CommandKind,
+    symbol: Option<String>,
+    dest: Option<String>,
+    comp: Option<String>,
+    jump: Option<String>,
+    line_number: isize,
+    source_line: usize,
+    error: Option<AssemblerError>,
+}
+
+impl ParsedLine {
+    /// Whether this is an A-instruction, eg `@100` or `@LOOP`.
+    pub fn is_a_instruction(&self) -> bool {
+        self.command_type == CommandKind::ACommand
+    }
+
+    /// Whether this is a C-instruction, eg `D=M+1;JEQ`.
+    pub fn is_c_instruction(&self) -> bool {
+        self.command_type == CommandKind::CCommand
+    }
+
+    /// An A-instruction's number or symbol, without the `@`.
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    /// A C-instruction's dest, if it has one.
+    pub fn dest(&self) -> Option<&str> {
+        self.dest.as_deref()
+    }
+
+    /// A C-instruction's comp.
+    pub fn comp(&self) -> Option<&str> {
+        self.comp.as_deref()
+    }
+
+    /// A C-instruction's jump, if it has one.
+    pub fn jump(&self) -> Option<&str> {
+        self.jump.as_deref()
+    }
+
+    /// The 1-based line of the source this came from.
+    pub fn source_line(&self) -> usize {
+        self.source_line
+    }
+
+    /// The ROM address the instruction is assembled to.
+    pub fn rom_address(&self) -> usize {
+        self.line_number as usize
+    }
+
+    fn words(&self) -> usize {
+        // How many words of ROM the line fills.
+        match self.command_type {
+            CommandKind::ACommand | CommandKind::CCommand | CommandKind::MCommand => 1,
+            CommandKind::OCommand(padding) => padding,
+            CommandKind::LCommand | CommandKind::ICommand(_) => 0,
+        }
+    }
+
+    fn mnemonic(&self) -> String {
+        // The line written back out as assembly, eg D=M+1;JEQ, with the spacing it was written with removed.
+        match self.command_type {
+            CommandKind::ACommand => format!("@{}", self.symbol.as_deref().unwrap()),
+            CommandKind::LCommand => format!("({})", self.symbol.as_deref().unwrap()),
+            CommandKind::OCommand(padding) => format!(".org {}", self.line_number as usize + padding),
+            _ => {
+                let dest = self.dest.as_ref().map(|x| format!("{}=", x)).unwrap_or_default();
+                let jump = self.jump.as_ref().map(|x| format!(";{}", x)).unwrap_or_default();
+                format!("{}{}{}", dest, self.comp.as_deref().unwrap_or_default(), jump)
+            }
+        }
+    }
+}
+
+/// What kind of name a symbol is, which decides where its address came from.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum SymbolKind {
+    Predefined, // One of the built in symbols, eg R0, SP or SCREEN.
+    Label,      // A (LABEL) declaration, resolving to a ROM address.
+    Constant,   // An address fixed from outside the program, eg by --symbols-in.
+    Variable,   // A RAM address allocated by the assembler on first use.
+}
+
+/// A resolved symbol: its address and what kind of name it is.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct SymbolEntry {
+    pub address: u16,
+    pub kind: SymbolKind,
+}
+
+/// Every symbol a program can refer to, by name.
+pub type SymbolTable = HashMap<String, SymbolEntry>;
+
+/// How big a program is: its instructions, the symbols it defines, and the lines with no code on them, which are
+/// either blank or nothing but a comment.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct ProgramCounts {
+    pub instructions: usize,
+    pub variables: usize,
+    pub labels: usize,
+    pub comments: usize,
+    pub blank: usize,
+}
+
+/// How much a program jumps around. A jump's target is only known when the A-instruction straight before it loads a
+/// number or symbol, so `targets` counts the distinct ROM addresses of those; the others are still counted as jumps.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct JumpMetrics {
+    pub jumps: usize,
+    pub conditional: usize,
+    pub unconditional: usize,
+    pub targets: usize,
+    /// The conditional jumps plus one, a rough measure of how many paths there are through the program.
+    pub branches: usize,
+}
+
+/// Everything that can go wrong while assembling a program. Each variant carries the 1-based source line.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum AssemblerError {
+    UnknownComp { line: usize, comp: String },
+    UnknownDest { line: usize, dest: String },
+    UnknownJump { line: usize, jump: String },
+    MissingComp { line: usize },
+    MalformedLine { line: usize, text: String, reason: &'static str },
+    InvalidAddress { line: usize, address: String },
+    UndefinedSymbol { line: usize, symbol: String },
+    MalformedSymbolEntry { line: usize, entry: String },
+    SeededPredefined { symbol: String },
+    SeededLabel { line: usize, symbol: String },
+    VariableAboveLimit { line: usize, symbol: String, address: isize, limit: u16 },
+    RomOverflow { words: usize, size: usize },
+    UnexpectedSymbol { line: usize, symbol: String },
+    ReservedLabel { line: usize, symbol: String },
+    VariableInExpression { line: usize, symbol: String },
+    DuplicateLabel { line: usize, symbol: String },
+    CountMismatch { expected: usize, emitted: usize },
+}
+
+impl AssemblerError {
+    /// The 1-based source line the error was found on, if it belongs to one.
+    pub fn line(&self) -> Option<usize> {
+        match self {
+            AssemblerError::UnknownComp { line, .. }
+            | AssemblerError::UnknownDest { line, .. }
+            | AssemblerError::UnknownJump { line, .. }
+            | AssemblerError::MissingComp { line }
+            | AssemblerError::MalformedLine { line, .. }
+            | AssemblerError::InvalidAddress { line, .. }
+            | AssemblerError::UndefinedSymbol { line, .. }
+            | AssemblerError::MalformedSymbolEntry { line, .. }
+            | AssemblerError::SeededLabel { line, .. }
+            | AssemblerError::VariableAboveLimit { line, .. }
+            | AssemblerError::UnexpectedSymbol { line, .. }
+            | AssemblerError::ReservedLabel { line, .. }
+            | AssemblerError::VariableInExpression { line, .. }
+            | AssemblerError::DuplicateLabel { line, .. } => Some(*line),
+            AssemblerError::SeededPredefined { .. }
+            | AssemblerError::RomOverflow { .. }
+            | AssemblerError::CountMismatch { .. } => None,
+        }
+    }
+
+    fn line_mut(&mut self) -> Option<&mut usize> {
+        match self {
+            AssemblerError::UnknownComp { line, .. }
+            | AssemblerError::UnknownDest { line, .. }
+            | AssemblerError::UnknownJump { line, .. }
+            | AssemblerError::MissingComp { line }
+            | AssemblerError::MalformedLine { line, .. }
+            | AssemblerError::InvalidAddress { line, .. }
+            | AssemblerError::UndefinedSymbol { line, .. }
+            | AssemblerError::MalformedSymbolEntry { line, .. }
+            | AssemblerError::SeededLabel { line, .. }
+            | AssemblerError::VariableAboveLimit { line, .. }
+            | AssemblerError::UnexpectedSymbol { line, .. }
+            | AssemblerError::ReservedLabel { line, .. }
+            | AssemblerError::VariableInExpression { line, .. }
+            | AssemblerError::DuplicateLabel { line, .. } => Some(line),
+            AssemblerError::SeededPredefined { .. }
+            | AssemblerError::RomOverflow { .. }
+            | AssemblerError::CountMismatch { .. } => None,
+        }
+    }
+
+    /// The 0-based character columns `(start, end)` of the part of `source_line` this error is about. The offending
+    /// token is matched ignoring whitespace, as the parser removes it. None if there is no token or it can't be found.
+    pub fn span(&self, source_line: &str) -> Option<(usize, usize)> {
+        let token = match self {
+            AssemblerError::UnknownComp { comp, .. } => comp,
+            AssemblerError::UnknownDest { dest, .. } => dest,
+            AssemblerError::UnknownJump { jump, .. } => jump,
+            AssemblerError::MalformedLine { text, .. } => text,
+            AssemblerError::InvalidAddress { address, .. } => address,
+            AssemblerError::UndefinedSymbol { symbol, .. }
+            | AssemblerError::SeededLabel { symbol, .. }
+            | AssemblerError::VariableAboveLimit { symbol, .. }
+            | AssemblerError::UnexpectedSymbol { symbol, .. }
+            | AssemblerError::ReservedLabel { symbol, .. }
+            | AssemblerError::VariableInExpression { symbol, .. }
+            | AssemblerError::DuplicateLabel { symbol, .. } => symbol,
+            AssemblerError::MissingComp { .. }
+            | AssemblerError::MalformedSymbolEntry { .. }
+            | AssemblerError::SeededPredefined { .. }
+            | AssemblerError::RomOverflow { .. }
+            | AssemblerError::CountMismatch { .. } => return None,
+        };
+        find_span(source_line, token)
+    }
+
+    /// A short name for the kind of error that stays the same whatever its details, eg `unknown-comp`, for tools that
+    /// want to tell errors apart without parsing the message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            AssemblerError::UnknownComp { .. } => "unknown-comp",
+            AssemblerError::UnknownDest { .. } => "unknown-dest",
+            AssemblerError::UnknownJump { .. } => "unknown-jump",
+            AssemblerError::MissingComp { .. } => "missing-comp",
+            AssemblerError::MalformedLine { .. } => "malformed-line",
+            AssemblerError::InvalidAddress { .. } => "invalid-address",
+            AssemblerError::UndefinedSymbol { .. } => "undefined-symbol",
+            AssemblerError::MalformedSymbolEntry { .. } => "malformed-symbol-entry",
+            AssemblerError::SeededPredefined { .. } => "seeded-predefined",
+            AssemblerError::SeededLabel { .. } => "seeded-label",
+            AssemblerError::VariableAboveLimit { .. } => "variable-above-limit",
+            AssemblerError::RomOverflow { .. } => "rom-overflow",
+            AssemblerError::UnexpectedSymbol { .. } => "unexpected-symbol",
+            AssemblerError::ReservedLabel { .. } => "reserved-label",
+            AssemblerError::VariableInExpression { .. } => "variable-in-expression",
+            AssemblerError::DuplicateLabel { .. } => "duplicate-label",
+            AssemblerError::CountMismatch { .. } => "count-mismatch",
+        }
+    }
+
+    /// What went wrong, without the line number.
+    pub fn message(&self) -> String {
+        match self {
+            AssemblerError::UnknownComp { comp, .. } => {
+                format!("unknown comp '{}'{}", comp, did_you_mean(canonical_comp(comp)))
+            }
+            AssemblerError::UnknownDest { dest, .. } => {
+                format!("unknown dest '{}'{}", dest, did_you_mean(canonical_dest(dest)))
+            }
+            AssemblerError::UnknownJump { jump, .. } => {
+                format!("unknown jump '{}'{}", jump, did_you_mean(canonical_jump(jump)))
+            }
+            AssemblerError::MissingComp { .. } => "C-instruction has no comp".to_string(),
+            AssemblerError::MalformedLine { text, reason, .. } => format!("malformed instruction '{}': {}", text, reason),
+            AssemblerError::InvalidAddress { address, .. } => {
+                format!("'{}' is not a valid address (0 to {})", address, MAX_A_VALUE)
+            }
+            AssemblerError::UndefinedSymbol { symbol, .. } => format!("symbol '{}' was never defined", symbol),
+            AssemblerError::MalformedSymbolEntry { entry, .. } => {
+                format!("'{}' is not a name=address symbol entry", entry)
+            }
+            AssemblerError::SeededPredefined { symbol } => {
+                format!("'{}' is a predefined symbol and cannot be seeded", symbol)
+            }
+            AssemblerError::SeededLabel { symbol, .. } => {
+                format!("label '{}' is already defined as a seeded symbol", symbol)
+            }
+            AssemblerError::VariableAboveLimit {
+                symbol, address, limit, ..
+            } => format!(
+                "variable '{}' was allocated address {}, above the limit of {}",
+                symbol, address, limit
+            ),
+            AssemblerError::RomOverflow { words, size } => {
+                format!("the program is {} words, too big for a ROM of {}", words, size)
+            }
+            AssemblerError::UnexpectedSymbol { symbol, .. } => {
+                format!("'{}' is a symbol, but the program was assumed to have none", symbol)
+            }
+            AssemblerError::ReservedLabel { symbol, .. } => {
+                format!("label '{}' starts with '{}', which is reserved for the assembler", symbol, RESERVED_PREFIX)
+            }
+            AssemblerError::VariableInExpression { symbol, .. } => {
+                format!("'{}' is a variable, which can't be used in an expression", symbol)
+            }
+            AssemblerError::DuplicateLabel { symbol, .. } => {
+                format!("label '{}' is already defined in another file", symbol)
+            }
+            AssemblerError::CountMismatch { expected, emitted } => format!(
+                "emitted {} words for a program of {} instructions, which is a bug in the assembler",
+                emitted, expected
+            ),
+        }
+    }
+}
+
+/* Only the book's exact spellings assemble. These find the one a rejected spelling was probably meant as, for the
+    error to suggest: the same letters in the other case, the operands of a +, & or | comp the other way round, or
+    the registers of a dest in the canonical order (eg DM for MD).
+*/
+fn canonical_comp(comp: &str) -> Option<&'static str> {
+    let comp = comp.to_uppercase();
+    let swapped = ['+', '&', '|'].iter().find_map(|op| {
+        let (left, right) = comp.split_once(*op)?;
+        Some(format!("{}{}{}", right, op, left))
+    });
+    COMP_TABLE
+        .iter()
+        .filter_map(|(mnemonic, _)| *mnemonic)
+        .find(|x| *x == comp || Some(x.to_string()) == swapped)
+}
+
+fn canonical_dest(dest: &str) -> Option<&'static str> {
+    let mut registers: Vec<char> = dest.to_uppercase().chars().collect();
+    registers.sort_unstable();
+    DEST_TABLE.iter().filter_map(|(mnemonic, _)| *mnemonic).find(|x| {
+        let mut canonical: Vec<char> = x.chars().collect();
+        canonical.sort_unstable();
+        canonical == registers
+    })
+}
+
+fn canonical_jump(jump: &str) -> Option<&'static str> {
+    let jump = jump.to_uppercase();
+    JUMP_TABLE.iter().filter_map(|(mnemonic, _)| *mnemonic).find(|x| *x == jump)
+}
+
+fn did_you_mean(canonical: Option<&str>) -> String {
+    canonical.map(|x| format!(", did you mean '{}'?", x)).unwrap_or_default()
+}
+
+fn find_span(source_line: &str, token: &str) -> Option<(usize, usize)> {
+    // Look for token among the non-whitespace characters of the line, then map it back to columns of the original.
+    let token: Vec<char> = token.chars().collect();
+    if token.is_empty() {
+        return None;
+    }
+    let visible: Vec<(usize, char)> = source_line.chars().enumerate().filter(|(_, x)| !x.is_whitespace()).collect();
+    visible
+        .windows(token.len())
+        .find(|window| window.iter().map(|(_, x)| *x).eq(token.iter().cloned()))
+        .map(|window| (window[0].0, window[window.len() - 1].0 + 1))
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line() {
+            Some(line) => write!(f, "line {}: {}", line, self.message()),
+            None => write!(f, "{}", self.message()),
+        }
+    }
+}
+
+impl std::error::Error for AssemblerError {}
+
+fn is_symbol_char(c: char) -> bool {
+    // The characters the course allows in a symbol. Whitespace has already gone, so "@ @5" arrives as "@@5" and is
+    // caught here rather than becoming a variable called "@5".
+    c.is_ascii_alphanumeric() || "_.$:".contains(c)
+}
+
+fn has_leftover(source_line: &str, instruction: &str) -> bool {
+    // Whether anything follows a label's closing ')', eg "(LOOP)x", or two words of symbol characters are separated
+    // only by whitespace, eg "M extra" in "D=M extra", which removing the whitespace would join into one. Anything
+    // after a "//" is a comment, not part of the line.
+    if instruction.starts_with('(') && instruction.find(')').is_some_and(|x| x + 1 != instruction.len()) {
+        return true;
+    }
+    let code = source_line.split("//").next().unwrap();
+    let words: Vec<&str> = code.split_whitespace().collect();
+    words.windows(2).any(|pair| {
+        pair[0].chars().last().is_some_and(is_symbol_char) && pair[1].chars().next().is_some_and(is_symbol_char)
+    })
+}
+
+fn parse_line(line: String, line_number: isize, source_line: usize, expr: bool) -> Result<ParsedLine, AssemblerError> {
+    // Assumes the line has been preprocessed already. That means all comments and whitespace have been removed and the
+    // line is not a comment. Therefore, everything to parse is a Hack Assembly Language command of some form, though
+    // not necessarily a well formed one.
+    let malformed = |reason| AssemblerError::MalformedLine {
+        line: source_line,
+        text: line.to_owned(),
+        reason,
+    };
+    let mut parsed = ParsedLine {
+        command_type: CommandKind::CCommand,
+        symbol: None,
+        dest: None,
+        comp: None,
+        jump: None,
+        line_number,
+        source_line,
+        error: None,
+    };
+
+    if let Some(symbol) = line.strip_prefix('@') {
+        // A instruction, take everything until EOL. Either a number (@100) or a label or variable (@dog).
+        if symbol.is_empty() {
+            return Err(malformed("nothing follows the '@'"));
+        }
+        if expr && is_expression(symbol) {
+            if !symbol.split(EXPRESSION_OPERATORS).all(|x| !x.is_empty() && x.chars().all(is_symbol_char)) {
+                return Err(malformed("an expression is numbers and symbols joined by '+' or '-'"));
+            }
+        } else if !symbol.chars().all(is_symbol_char) {
+            return Err(malformed("a symbol may only contain letters, digits, '_', '.', '$' and ':'"));
+        }
+        parsed.command_type = CommandKind::ACommand;
+        parsed.symbol = Some(symbol.to_string());
+    } else if let Some(label) = line.strip_prefix('(') {
+        // We're a nice LOOP label of form (XXX); take just the XXX
+        let name = match label.split_once(')') {
+            Some((name, _)) => name,
+            None => return Err(malformed("the label has no closing ')'")),
+        };
+        if name.is_empty() {
+            return Err(malformed("the label has no name"));
+        }
+        parsed.command_type = CommandKind::LCommand;
+        parsed.symbol = Some(name.to_string());
+        if !parsed.symbol.as_ref().unwrap().chars().all(is_symbol_char) {
+            return Err(malformed("a label may only contain letters, digits, '_', '.', '$' and ':'"));
+        }
+    } else {
+        /* A C instruction, dest=comp;jump, where "dest=" and ";jump" are both optional. Split the line on its '=' and
+        ';' rather than picking out the characters around them, after checking they are in that order:
+            D=M+1;JEQ --> dest: D, comp: M+1, jump: JEQ
+            D;JGT     --> dest: None, comp: D, jump: JGT
+        Each part is checked against the opcode tables when translating.
+        */
+        if line.chars().all(|x| "=;+-!&|".contains(x)) {
+            // Eg a stray "+" or ";", which would otherwise be reported as a comp that doesn't exist.
+            return Err(malformed("an operator on its own is not an instruction"));
+        }
+        if line.matches('=').count() > 1 {
+            return Err(malformed("more than one '='"));
+        }
+        if line.matches(';').count() > 1 {
+            return Err(malformed("more than one ';'"));
+        }
+        let (dest, rest) = match line.split_once('=') {
+            Some((dest, rest)) => (Some(dest), rest),
+            None => (None, line.as_str()),
+        };
+        if dest == Some("") {
+            return Err(malformed("nothing comes before the '='"));
+        }
+        if dest.is_some_and(|x| x.contains(';')) {
+            return Err(malformed("the ';' jump comes before the '=' dest"));
+        }
+        let (comp, jump) = match rest.split_once(';') {
+            Some((comp, jump)) => (comp, Some(jump)),
+            None => (rest, None),
+        };
+        if jump == Some("") {
+            // Eg "D=M;", a typo more likely than a jump left out on purpose, which would be written without the ';'.
+            return Err(malformed("nothing follows the ';'"));
+        }
+        if let Some(jump) = jump.filter(|x| x.len() < 3) {
+            // A line cut off part way through its jump, eg "0;J", rather than a mnemonic that doesn't exist.
+            if JUMP_TABLE.iter().flat_map(|(x, _)| x).any(|x| x.starts_with(jump)) {
+                return Err(malformed("the jump mnemonic is cut short"));
+            }
+        }
+        parsed.dest = dest.map(String::from);
+        parsed.comp = Some(comp.to_string()).filter(|x| !x.is_empty());
+        parsed.jump = jump.map(String::from);
+    }
+    Ok(parsed)
+}
+
+/// One line of a program with its comment split off and its whitespace removed, ready to be parsed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PreprocessedLine {
+    /// The instruction or label with every space removed, or None for a blank or comment-only line.
+    pub instruction: Option<String>,
+    /// The 1-based line of the source it came from.
+    pub source_line: usize,
+    /// The text after the line's `//`, trimmed, if it has a comment.
+    pub comment: Option<String>,
+    /// How many whitespace characters the line starts with.
+    pub indent: usize,
+}
+
+/// Split a program into lines and strip each one's comment and whitespace, the step before parsing. Every line of the
+/// source gives one PreprocessedLine, in order, blank ones included.
+pub fn preprocess(source: &str) -> Vec<PreprocessedLine> {
+    source
+        .lines()
+        .enumerate()
+        .map(|(index, line)| {
+            let (instruction, comment) = preprocess_line(line);
+            PreprocessedLine {
+                instruction,
+                source_line: index + 1,
+                comment,
+                indent: line.chars().take_while(|x| x.is_whitespace()).count(),
+            }
+        })
+        .collect()
+}
+
+fn preprocess_line(line: &str) -> (Option<String>, Option<String>) {
+    // Strip comments, whitespaces, and spaces between words from each line, keeping the comment to one side.
+    /* Lines can be comments, empty, an instruction or label, or a combo of an instruction and comment.
+    Examples:
+    // File: add.asm
+    // adds 100 to whatever's at register 300 and stores it at register 100
+    @300
+    D = M
+    @100
+    M = D + A
+
+    The above should become:
+    @300
+    D=M
+    @100
+    M=D+A
+     */
+    let (code, comment) = match line.find("//") {
+        Some(comment_start) => (&line[..comment_start], Some(line[comment_start + 2..].trim().to_string())),
+        None => (line, None),
+    };
+    let potential_instruction = code.split_whitespace().collect::<String>();
+    if potential_instruction.is_empty() {
+        (None, comment)
+    } else {
+        (Some(potential_instruction), comment)
+    }
+}
+
+fn parse_each_line(contents: &str, options: &Options) -> Vec<ParsedLine> {
+    // Parse each line and return a vector containing them all, to avoid reparsing the document later on.
+    // If we have an L_command we need to decrement the line-number
+    // A line that doesn't parse becomes an MCommand holding its error, to be reported when it is translated. Until
+    // then the symbol passes skip over it, so one bad line doesn't shift the addresses of the labels after it.
+    // With local labels, a symbol starting with '.' is renamed into the scope of the last ordinary label, eg @.loop
+    // after (MAIN) becomes @MAIN.loop. Before the first label there is no scope and it is left as it is.
+    // Instructions are numbered from options.base_rom, which is where the first one is loaded, until a .org moves the
+    // numbering on to its address.
+    let mut parsed_lines: Vec<ParsedLine> = vec![];
+    let mut line_number = options.base_rom as isize - 1;
+    let mut scope: Option<String> = None;
+    for (line, text) in preprocess(contents).into_iter().zip(contents.lines()) {
+        let index = line.source_line - 1;
+        let mut preproc_line = match line.instruction {
+            Some(instr) if instr.starts_with(".org") => {
+                let start = line_number + 1;
+                let parsed = match instr[4..].parse::<u16>() {
+                    Ok(address) if address > MAX_A_VALUE => Err("the address is past the end of the ROM"),
+                    Ok(address) if (address as isize) < start => {
+                        Err("the address is before instructions that have already been placed")
+                    }
+                    Ok(address) => Ok(address as isize),
+                    Err(_) => Err("'.org' needs a ROM address"),
+                };
+                match parsed {
+                    Ok(address) => {
+                        line_number = address - 1;
+                        ParsedLine {
+                            command_type: CommandKind::OCommand((address - start) as usize),
+                            symbol: None,
+                            dest: None,
+                            comp: None,
+                            jump: None,
+                            line_number: start,
+                            source_line: index + 1,
+                            error: None,
+                        }
+                    }
+                    Err(reason) => {
+                        line_number += 1;
+                        ParsedLine {
+                            command_type: CommandKind::MCommand,
+                            symbol: None,
+                            dest: None,
+                            comp: None,
+                            jump: None,
+                            line_number,
+                            source_line: index + 1,
+                            error: Some(AssemblerError::MalformedLine {
+                                line: index + 1,
+                                text: instr,
+                                reason,
+                            }),
+                        }
+                    }
+                }
+            }
+            Some(mut instr) => {
+                if options.jmp_shorthand && JUMP_TABLE.iter().any(|(jump, _)| *jump == Some(instr.as_str())) {
+                    // A bare jump mnemonic like "JMP" is shorthand for "0;JMP".
+                    instr = format!("0;{}", instr);
+                }
+                line_number += 1;
+                let misplaced = match (instr.starts_with('('), line.indent > 0) {
+                    _ if !options.column_strict => None,
+                    (true, true) => Some("a label must start in the first column"),
+                    (false, false) => Some("an instruction must be indented"),
+                    _ => None,
+                };
+                let leftover = options.strict_eol && has_leftover(text, &instr);
+                let parsed = match (options.max_line_len, misplaced) {
+                    (Some(max), _) if instr.len() > max => Err(AssemblerError::MalformedLine {
+                        line: index + 1,
+                        text: instr.chars().take(20).collect(),
+                        reason: "the instruction is longer than the maximum allowed",
+                    }),
+                    (_, Some(reason)) => Err(AssemblerError::MalformedLine {
+                        line: index + 1,
+                        text: instr,
+                        reason,
+                    }),
+                    _ if leftover => Err(AssemblerError::MalformedLine {
+                        line: index + 1,
+                        text: instr,
+                        reason: "there is more on the line after the instruction",
+                    }),
+                    _ => parse_line(instr, line_number, index + 1, options.expr),
+                };
+                parsed.unwrap_or_else(|e| ParsedLine {
+                    command_type: CommandKind::MCommand,
+                    symbol: None,
+                    dest: None,
+                    comp: None,
+                    jump: None,
+                    line_number,
+                    source_line: index + 1,
+                    error: Some(e),
+                })
+            }
+            None => ParsedLine {
+                command_type: CommandKind::ICommand(if line.comment.is_none() {
+                    IgnoreReason::Blank
+                } else {
+                    IgnoreReason::Comment
+                }),
+                symbol: None,
+                dest: None,
+                comp: None,
+                jump: None,
+                line_number: 0,
+                source_line: 0,
+                error: None,
+            },
+        };
+        if preproc_line.command_type == CommandKind::LCommand {
+            line_number -= 1;
+        }
+        if options.local_labels {
+            if let Some(symbol) = preproc_line.symbol.as_mut() {
+                if symbol.starts_with('.') {
+                    if let Some(scope) = &scope {
+                        symbol.insert_str(0, scope);
+                    }
+                } else if preproc_line.command_type == CommandKind::LCommand {
+                    scope = Some(symbol.to_owned());
+                }
+            }
+        }
+        preproc_line.source_line = index + 1;
+        parsed_lines.push(preproc_line);
+    }
+    parsed_lines
+}
+
+// The dest, jump, and comp mnemonics and their bits, in the order the course lists them. A missing dest or
+// jump (None) encodes as zeros; comp is mandatory.
+const DEST_TABLE: [(Option<&str>, &str); 8] = [
+    (None, "000"),
+    (Some("M"), "001"),
+    (Some("D"), "010"),
+    (Some("MD"), "011"),
+    (Some("A"), "100"),
+    (Some("AM"), "101"),
+    (Some("AD"), "110"),
+    (Some("AMD"), "111"),
+];
+
+const JUMP_TABLE: [(Option<&str>, &str); 8] = [
+    (None, "000"),
+    (Some("JGT"), "001"),
+    (Some("JEQ"), "010"),
+    (Some("JGE"), "011"),
+    (Some("JLT"), "100"),
+    (Some("JNE"), "101"),
+    (Some("JLE"), "110"),
+    (Some("JMP"), "111"),
+];
+
+const COMP_TABLE: [(Option<&str>, &str); 28] = [
+    (Some("0"), "0101010"),
+    (Some("1"), "0111111"),
+    (Some("-1"), "0111010"),
+    (Some("D"), "0001100"),
+    (Some("A"), "0110000"),
+    (Some("M"), "1110000"),
+    (Some("!D"), "0001101"),
+    (Some("!A"), "0110001"),
+    (Some("!M"), "1110001"),
+    (Some("-D"), "0001111"),
+    (Some("-A"), "0110011"),
+    (Some("-M"), "1110011"),
+    (Some("D+1"), "0011111"),
+    (Some("A+1"), "0110111"),
+    (Some("M+1"), "1110111"),
+    (Some("D-1"), "0001110"),
+    (Some("A-1"), "0110010"),
+    (Some("M-1"), "1110010"),
+    (Some("D+A"), "0000010"),
+    (Some("D+M"), "1000010"),
+    (Some("D-A"), "0010011"),
+    (Some("D-M"), "1010011"),
+    (Some("A-D"), "0000111"),
+    (Some("M-D"), "1000111"),
+    (Some("D&A"), "0000000"),
+    (Some("D&M"), "1000000"),
+    (Some("D|A"), "0010101"),
+    (Some("D|M"), "1010101"),
+];
+
+fn opcode_bits(table: &[(Option<&str>, &'static str)], mnemonic: &Option<String>) -> Option<&'static str> {
+    // The tables are small enough that scanning one is quicker than hashing the mnemonic, let alone building a map.
+    table.iter().find(|(x, _)| *x == mnemonic.as_deref()).map(|(_, bits)| *bits)
+}
+
+const MAX_A_VALUE: u16 = 0x7FFF; // A-instructions start with a 0 bit, leaving 15 bits for the value.
+
+fn a_instruction_value(instruction: &ParsedLine, address: isize) -> Result<u16, AssemblerError> {
+    // Anything wider than 15 bits would set the leading bit and silently turn into a C-instruction.
+    if (0..=MAX_A_VALUE as isize).contains(&address) {
+        Ok(address as u16)
+    } else {
+        Err(AssemblerError::InvalidAddress {
+            line: instruction.source_line,
+            address: address.to_string(),
+        })
+    }
+}
+
+const C_PREFIX: &str = "111"; // The bits every C-instruction starts with, ahead of its comp, dest and jump.
+
+fn no_op(c_prefix: &str) -> String {
+    // NOP with `c_prefix` in place of its leading bits, the way any other C-instruction is built. It fills the gap a
+    // .org leaves.
+    c_prefix.to_string() + &format!("{:016b}", NOP)[C_PREFIX.len()..]
+}
+
+fn emitted_words<'p>(
+    parsed_line: &'p ParsedLine,
+    symbol_table: &SymbolTable,
+    c_prefix: &str,
+) -> Result<impl Iterator<Item = (usize, String)> + 'p, AssemblerError> {
+    // The words a line puts in ROM, each with its address: an instruction's own word, a no-op for each word of
+    // padding from a .org, and nothing for anything else.
+    let word = match parsed_line.command_type {
+        CommandKind::ACommand | CommandKind::CCommand | CommandKind::MCommand => {
+            translate(parsed_line, symbol_table, c_prefix)?
+        }
+        CommandKind::OCommand(_) => no_op(c_prefix),
+        CommandKind::LCommand | CommandKind::ICommand(_) => String::new(),
+    };
+    let first = parsed_line.line_number as usize;
+    Ok(std::iter::repeat_n(word, parsed_line.words()).enumerate().map(move |(offset, word)| (first + offset, word)))
+}
+
+fn c_prefix(options: &Options) -> String {
+    match options.c_prefix {
+        Some(bits) => format!("{:03b}", bits & 0b111),
+        None => C_PREFIX.to_string(),
+    }
+}
+
+const EXPRESSION_OPERATORS: [char; 2] = ['+', '-'];
+
+fn is_expression(symbol: &str) -> bool {
+    // Only true with Options::expr, as otherwise parsing rejects an A-instruction with an operator in it.
+    symbol.contains(EXPRESSION_OPERATORS)
+}
+
+fn evaluate_expression(instruction: &ParsedLine, symbol_table: &SymbolTable) -> Result<isize, AssemblerError> {
+    // Work through an expression like SCREEN+32-1 one operand at a time, left to right. Every operand is a valid
+    // address, but enough of them could still overflow the total, which is reported as an invalid address too.
+    let expression = instruction.symbol.as_deref().unwrap();
+    let invalid = |address: &str| AssemblerError::InvalidAddress {
+        line: instruction.source_line,
+        address: address.to_owned(),
+    };
+    let mut value: isize = 0;
+    let mut sign = 1;
+    for term in expression.split_inclusive(EXPRESSION_OPERATORS) {
+        // Each term is an operand followed by the operator applying to the next one, if there is a next one.
+        let (operand, next_sign) = match term.strip_suffix('-') {
+            Some(operand) => (operand, -1),
+            None => (term.trim_end_matches('+'), 1),
+        };
+        let operand_value = if operand.chars().next().unwrap().is_numeric() {
+            match operand.parse::<u16>() {
+                Ok(number) if number <= MAX_A_VALUE => number as isize,
+                _ => return Err(invalid(operand)),
+            }
+        } else {
+            match symbol_table.get(operand) {
+                Some(entry) if entry.kind == SymbolKind::Variable => {
+                    return Err(AssemblerError::VariableInExpression {
+                        line: instruction.source_line,
+                        symbol: operand.to_owned(),
+                    })
+                }
+                Some(entry) => entry.address as isize,
+                None => {
+                    return Err(AssemblerError::UndefinedSymbol {
+                        line: instruction.source_line,
+                        symbol: operand.to_owned(),
+                    })
+                }
+            }
+        };
+        value = match sign {
+            1 => value.checked_add(operand_value),
+            _ => value.checked_sub(operand_value),
+        }
+        .ok_or_else(|| invalid(expression))?;
+        sign = next_sign;
+    }
+    Ok(value)
+}
+
+fn translate(instruction: &ParsedLine, symbol_table: &SymbolTable, c_prefix: &str) -> Result<String, AssemblerError> {
+    /* Translate the parsed content into their corrosponding binary instructions.
+    Each piece of ParsedLine (except LCommands, which are special) has one and only one binary representation.
+    A instructions are just translated into the binary representation of their symbol, with a leading 0 eg:
+        @2 --> 0000000000000010
+    LCommands have a non-number value as their symbol and require consulting a symbol table we populated earlier.
+        @dog --> | dog | 16 | --> 0000000000010000
+    C instructions have multiple parts, one per field with three leading 1s (or whatever c_prefix is):
+        D=A+1;JMP ->  111accccccdddjjj where acccccc are determined by the comp, ddd by dest, and jjj by jump.
+    */
+    if let Some(e) = &instruction.error {
+        // The line never parsed; this is the first point it can be reported.
+        Err(e.to_owned())
+    } else if instruction.command_type == CommandKind::ACommand {
+        let symbol = instruction.symbol.to_owned().unwrap();
+        if is_expression(&symbol) {
+            let address = evaluate_expression(instruction, symbol_table)?;
+            Ok(format!("{:016b}", a_instruction_value(instruction, address)?))
+        } else if symbol.chars().next().unwrap().is_numeric() {
+            let address = symbol.parse::<isize>().map_err(|_| AssemblerError::InvalidAddress {
+                line: instruction.source_line,
+                address: symbol.to_owned(),
+            })?;
+            // Pad the ouput with enough zeros to "become" a 16-bit word.
+            Ok(format!("{:016b}", a_instruction_value(instruction, address)?))
+        } else {
+            // We're not numeric, so we're some sort of label (eg @cat)
+            let symbol_from_table = symbol_table
+                .get(&symbol)
+                .ok_or_else(|| AssemblerError::UndefinedSymbol {
+                    line: instruction.source_line,
+                    symbol: symbol.to_owned(),
+                })?;
+            Ok(format!(
+                "{:016b}",
+                a_instruction_value(instruction, symbol_from_table.address as isize)?
+            ))
+        }
+    } else {
+        // We're a C instruction. The word is 111accccccdddjjj:
+        let line = instruction.source_line;
+        let comp_bits = match opcode_bits(&COMP_TABLE, &instruction.comp) {
+            Some(bits) => bits,
+            None => {
+                return Err(match &instruction.comp {
+                    Some(comp) => AssemblerError::UnknownComp {
+                        line,
+                        comp: comp.to_owned(),
+                    },
+                    None => AssemblerError::MissingComp { line },
+                })
+            }
+        };
+        let dest_bits = opcode_bits(&DEST_TABLE, &instruction.dest).ok_or_else(|| AssemblerError::UnknownDest {
+            line,
+            dest: instruction.dest.to_owned().unwrap_or_default(),
+        })?;
+        let jump_bits = opcode_bits(&JUMP_TABLE, &instruction.jump).ok_or_else(|| AssemblerError::UnknownJump {
+            line,
+            jump: instruction.jump.to_owned().unwrap_or_default(),
+        })?;
+        Ok(c_prefix.to_string() + comp_bits + dest_bits + jump_bits)
+    }
+}
+
+fn populate_symbol_table(
+    label: &ParsedLine,
+    mut table: SymbolTable,
+    last_address: isize,
+) -> Result<(SymbolTable, isize), AssemblerError> {
+    // Takes a command and writes the label to the first free address in memory.
+    // The Hack language uses 0-15 as pre-set symbols; any other command will be allocated at 16 or higher until we
+    // hit the screen address - 1, at which point we're out of space.
+    // Unless the label is a loop declaration (XXX), which needs to be mapped to the line number it occurs on.
+
+    let symbol = label.symbol.to_owned().unwrap();
+    let mut address_to_assign = last_address;
+    if table.contains_key(&symbol) && label.command_type != CommandKind::LCommand {
+        Ok((table, last_address))
+    } else {
+        let entry = if label.command_type == CommandKind::LCommand {
+            SymbolEntry {
+                address: a_instruction_value(label, label.line_number)?,
+                kind: SymbolKind::Label,
+            }
+        } else {
+            address_to_assign = last_address + 1;
+            SymbolEntry {
+                address: a_instruction_value(label, address_to_assign)?,
+                kind: SymbolKind::Variable,
+            }
+        };
+        log::debug!("{:?} '{}' is at {}", entry.kind, symbol, entry.address);
+        table.insert(symbol, entry);
+        Ok((table, address_to_assign))
+    }
+}
+
+fn first_pass(
+    parsed_lines: &[ParsedLine],
+    mut symbol_table: SymbolTable,
+    options: &Options,
+    warnings: &mut Warnings,
+) -> Result<SymbolTable, AssemblerError> {
+    /* Iterate through each parsed line and populate the symbol table in two steps.
+    The two steps are needed as the first will only add LCommands, and the second does every non-numeric ACommand.
+    They cannot be done in the same loop as we do not want an ACommand that addresses a loop to be given an address
+    because that address will then be "taken", but the LCommand will overwrite the assigned address.
+    EG we don't want to give @LOOP an address as (LOOP) will overwrite it, and no other ACommand will get that address.
+    The resulting allocation contract: variables get RAM addresses from 16 upwards in order of their first reference,
+    every later reference reuses that address, and labels never use up a RAM slot wherever they appear. So
+    @i (LOOP) @i @j gives i -> 16 and j -> 17. Addresses owned by seeded symbols are skipped, but not the values of
+    constants, which aren't RAM.
+    */
+    let reserved_ram: Vec<isize> = seeded_ram(options).map(|(_, address)| address as isize).collect();
+
+    // This is the first loop, where we only populate (XXX) symbols into the table
+    let mut last_ram_address: isize = 15;
+    let instruction_count =
+        parsed_lines.iter().map(|x| x.words()).sum::<usize>() as isize + options.base_rom as isize;
+    for parsed_line in parsed_lines {
+        if parsed_line.command_type == CommandKind::LCommand {
+            let label = parsed_line.symbol.to_owned().unwrap();
+            if let Some(existing) = symbol_table.get(&label) {
+                let redefined = if existing.kind == SymbolKind::Predefined {
+                    "the predefined symbol"
+                } else {
+                    "an earlier label"
+                };
+                warnings.warn(
+                    WarningKind::Shadowing,
+                    parsed_line.source_line,
+                    format!("label '{}' redefines {} of the same name", label, redefined),
+                );
+            }
+            if parsed_line.line_number >= instruction_count {
+                warnings.warn(
+                    WarningKind::DanglingLabel,
+                    parsed_line.source_line,
+                    format!("label '{}' points past the last instruction", label),
+                );
+            }
+            let (symbol_table_destr, last_addr) =
+                populate_symbol_table(parsed_line, symbol_table, last_ram_address)?;
+            symbol_table = symbol_table_destr;
+            last_ram_address = last_addr;
+        }
+    }
+
+    // Handle A commands that are not an address
+    for parsed_line in parsed_lines {
+        if parsed_line.command_type == CommandKind::ACommand
+            && !parsed_line.symbol.as_ref().unwrap().chars().next().unwrap().is_numeric()
+            && !is_expression(parsed_line.symbol.as_ref().unwrap())
+        {
+            if !symbol_table.contains_key(parsed_line.symbol.as_ref().unwrap()) {
+                if options.require_decl {
+                    return Err(AssemblerError::UndefinedSymbol {
+                        line: parsed_line.source_line,
+                        symbol: parsed_line.symbol.to_owned().unwrap(),
+                    });
+                }
+                while reserved_ram.contains(&(last_ram_address + 1)) {
+                    last_ram_address += 1;
+                }
+            }
+            let (symbol_table_a, loop_lines_b) =
+                populate_symbol_table(parsed_line, symbol_table, last_ram_address)?;
+            symbol_table = symbol_table_a;
+            if let Some(limit) = options.max_var_addr {
+                if loop_lines_b != last_ram_address && loop_lines_b > limit as isize {
+                    return Err(AssemblerError::VariableAboveLimit {
+                        line: parsed_line.source_line,
+                        symbol: parsed_line.symbol.to_owned().unwrap(),
+                        address: loop_lines_b,
+                        limit,
+                    });
+                }
+            }
+            if loop_lines_b != last_ram_address && loop_lines_b >= SCREEN as isize {
+                warnings.warn(
+                    WarningKind::ScreenOverlap,
+                    parsed_line.source_line,
+                    format!(
+                        "variable '{}' was allocated address {}, inside the SCREEN/KBD memory map",
+                        parsed_line.symbol.to_owned().unwrap(),
+                        loop_lines_b
+                    ),
+                );
+            }
+            last_ram_address = loop_lines_b;
+        }
+    }
+    Ok(symbol_table)
+}
+
+fn second_pass(
+    parsed_lines: &[ParsedLine],
+    symbol_table: &SymbolTable,
+    options: &Options,
+) -> Result<String, AssemblerError> {
+    // Do the second pass of translating the lines. Each word is 16 characters plus its newline, so reserve enough
+    // space up front rather than regrowing the output for every instruction.
+    let mut translated_contents = String::with_capacity(parsed_lines.len() * 17);
+    let c_prefix = c_prefix(options);
+    let mut emitted = 0;
+    for parsed_line in parsed_lines {
+        for (_, word) in emitted_words(parsed_line, symbol_table, &c_prefix)? {
+            if !translated_contents.is_empty() {
+                translated_contents.push('\n');
+            }
+            log::trace!("line {}: {}", parsed_line.source_line, word);
+            translated_contents.push_str(&word);
+            emitted += 1;
+        }
+    }
+    // Every instruction, and every word of .org padding, is one word: no more and no fewer.
+    let expected = parsed_lines.iter().map(|x| x.words()).sum();
+    if options.verify_count && emitted != expected {
+        return Err(AssemblerError::CountMismatch { expected, emitted });
+    }
+    debug_assert_eq!(emitted, expected, "the number of words emitted doesn't match the program");
+    Ok(translated_contents)
+}
+
+// The memory-mapped I/O addresses. Everything in RAM below SCREEN is general purpose.
+const SCREEN: u16 = 16384;
+const KBD: u16 = 24576;
+
+// The pre-set symbols every Hack program starts with.
+const PREDEFINED_SYMBOLS: [(&str, u16); 23] = [
+    ("SP", 0),
+    ("R0", 0),
+    ("LCL", 1),
+    ("R1", 1),
+    ("ARG", 2),
+    ("R2", 2),
+    ("THIS", 3),
+    ("R3", 3),
+    ("THAT", 4),
+    ("R4", 4),
+    ("R5", 5),
+    ("R6", 6),
+    ("R7", 7),
+    ("R8", 8),
+    ("R9", 9),
+    ("R10", 10),
+    ("R11", 11),
+    ("R12", 12),
+    ("R13", 13),
+    ("R14", 14),
+    ("R15", 15),
+    ("SCREEN", SCREEN),
+    ("KBD", KBD),
+];
+
+fn predefined_symbols() -> SymbolTable {
+    PREDEFINED_SYMBOLS
+        .iter()
+        .map(|(name, address)| {
+            let entry = SymbolEntry {
+                address: *address,
+                kind: SymbolKind::Predefined,
+            };
+            (name.to_string(), entry)
+        })
+        .collect()
+}
+
+fn seeded_ram(options: &Options) -> impl Iterator<Item = (&str, u16)> {
+    // The seeded symbols that take effect: the last of each name, unless a constant of the same name replaces it.
+    let seeded = &options.seeded_symbols;
+    seeded
+        .iter()
+        .enumerate()
+        .filter(move |(index, (name, _))| {
+            !seeded[index + 1..].iter().any(|(x, _)| x == name) && !options.constants.iter().any(|(x, _)| x == name)
+        })
+        .map(|(_, (name, address))| (name.as_str(), *address))
+}
+
+fn build_symbol_table(
+    parsed_lines: &[ParsedLine],
+    options: &Options,
+    warnings: &mut Warnings,
+) -> Result<SymbolTable, AssemblerError> {
+    // Seed the predefined and user-supplied symbols, then run the first pass over the program. Seeded symbols may
+    // not redefine a predefined name or be redefined by a label, as either would silently move them.
+    // Past the end of the ROM an instruction has no address an A-instruction could hold, so a program that big can't
+    // be loaded, let alone jump around in. This is checked first, as it applies even with nothing to resolve.
+    let words = parsed_lines.iter().map(|x| x.words()).sum::<usize>() + options.base_rom as usize;
+    if words > ROM_SIZE {
+        return Err(AssemblerError::RomOverflow { words, size: ROM_SIZE });
+    }
+    if options.assume_resolved {
+        // Nothing to resolve, so only check that there really is nothing, and leave the table empty.
+        let symbolic = parsed_lines.iter().find(|x| match x.command_type {
+            CommandKind::LCommand => true,
+            CommandKind::ACommand => !x.symbol.as_ref().unwrap().starts_with(|c: char| c.is_numeric()),
+            _ => false,
+        });
+        return match symbolic {
+            Some(parsed_line) => Err(AssemblerError::UnexpectedSymbol {
+                line: parsed_line.source_line,
+                symbol: parsed_line.symbol.to_owned().unwrap(),
+            }),
+            None => Ok(SymbolTable::new()),
+        };
+    }
+    let mut symbol_table = if options.no_predefined {
+        SymbolTable::new()
+    } else {
+        predefined_symbols()
+    };
+    let constants = options.constants.iter().map(|(name, value)| (name.as_str(), *value));
+    for (name, address) in seeded_ram(options).chain(constants) {
+        // A name given more than once is simply replaced, the last one winning.
+        if !options.no_predefined && PREDEFINED_SYMBOLS.iter().any(|(x, _)| *x == name) {
+            return Err(AssemblerError::SeededPredefined { symbol: name.to_owned() });
+        }
+        let entry = SymbolEntry {
+            address,
+            kind: SymbolKind::Constant,
+        };
+        symbol_table.insert(name.to_owned(), entry);
+    }
+    for parsed_line in parsed_lines {
+        if parsed_line.command_type == CommandKind::LCommand {
+            let symbol = parsed_line.symbol.to_owned().unwrap();
+            if options.seeded_symbols.iter().chain(&options.constants).any(|(name, _)| *name == symbol) {
+                return Err(AssemblerError::SeededLabel {
+                    line: parsed_line.source_line,
+                    symbol,
+                });
+            }
+            if options.strict && symbol.starts_with(RESERVED_PREFIX) {
+                return Err(AssemblerError::ReservedLabel {
+                    line: parsed_line.source_line,
+                    symbol,
+                });
+            }
+        }
+    }
+    if options.warn_confusable {
+        // Each name is only reported the first time it appears.
+        let mut reported = HashSet::new();
+        for parsed_line in parsed_lines {
+            if let CommandKind::ACommand | CommandKind::LCommand = parsed_line.command_type {
+                let symbol = parsed_line.symbol.as_deref().unwrap();
+                // The comps include 0 and 1, which as A-instructions are just numbers.
+                let is_mnemonic = !symbol.starts_with(|c: char| c.is_numeric())
+                    && DEST_TABLE
+                        .iter()
+                        .chain(COMP_TABLE.iter())
+                        .chain(JUMP_TABLE.iter())
+                        .any(|(mnemonic, _)| *mnemonic == Some(symbol));
+                if is_mnemonic && reported.insert(symbol) {
+                    warnings.warn(
+                        WarningKind::Confusable,
+                        parsed_line.source_line,
+                        format!("symbol '{}' has the same name as a register or mnemonic", symbol),
+                    );
+                }
+            }
+        }
+    }
+    first_pass(parsed_lines, symbol_table, options, warnings)
+}
+
+/// Read a symbol file of `name=address` (or tab separated `name<TAB>address`) lines, as used by
+/// `Options::seeded_symbols`. Blank lines and `//` comments are skipped.
+pub fn parse_symbol_file(contents: &str) -> Result<Vec<(String, u16)>, AssemblerError> {
+    let mut symbols = vec![];
+    for (index, line) in contents.lines().enumerate() {
+        let entry = line.trim();
+        if entry.is_empty() || entry.starts_with("//") {
+            continue;
+        }
+        let malformed = || AssemblerError::MalformedSymbolEntry {
+            line: index + 1,
+            entry: entry.to_string(),
+        };
+        let mut parts = entry.splitn(2, ['=', '\t']);
+        let name = parts.next().unwrap().trim();
+        let address = parts.next().ok_or_else(malformed)?.trim();
+        if name.is_empty() {
+            return Err(malformed());
+        }
+        let address = address.parse::<u16>().map_err(|_| malformed())?;
+        symbols.push((name.to_string(), address));
+    }
+    Ok(symbols)
+}
+
+/// Write a program's symbols, other than the predefined ones, as `name=address` lines in address order, the
+/// format `parse_symbol_file` reads.
+pub fn format_symbol_file(symbol_table: &SymbolTable) -> String {
+    let mut symbols: Vec<(&String, &SymbolEntry)> = symbol_table
+        .iter()
+        .filter(|(_, entry)| entry.kind != SymbolKind::Predefined)
+        .collect();
+    symbols.sort_by(|a, b| (a.1.address, a.0).cmp(&(b.1.address, b.0)));
+    symbols.iter().map(|(name, entry)| format!("{}={}\n", name, entry.address)).collect()
+}
+
+/// Compare a program's symbols against expected `name=address` pairs, eg read from a golden file with
+/// `parse_symbol_file`. Returns each expected symbol whose address differs, with the address it actually has, or None
+/// if the program doesn't define it. Symbols the program has beyond those expected aren't reported.
+pub fn symbol_mismatches(symbol_table: &SymbolTable, expected: &[(String, u16)]) -> Vec<(String, u16, Option<u16>)> {
+    expected
+        .iter()
+        .filter_map(|(name, address)| {
+            let actual = symbol_table.get(name).map(|x| x.address);
+            match actual {
+                Some(x) if x == *address => None,
+                _ => Some((name.to_owned(), *address, actual)),
+            }
+        })
+        .collect()
+}
+
+/// A program as it goes through the assembler: its parsed lines and, once resolved, its symbols. Parse it, resolve its
+/// symbols, then encode it, keeping hold of the result of each step rather than starting again from the source.
+#[derive(Debug, Clone)]
+pub struct Program<'a> {
+    source: &'a str,
+    lines: Vec<ParsedLine>,
+    symbols: SymbolTable,
+    options: Options,
+}
+
+impl<'a> Program<'a> {
+    /// Split a program into instructions. A line that fails to parse is kept, and reported when the program is
+    /// encoded.
+    pub fn parse(source: &'a str, options: &Options) -> Program<'a> {
+        let lines = parse_each_line(source, options);
+        log::info!("parsed {} lines", lines.len());
+        Program {
+            source,
+            lines,
+            symbols: SymbolTable::new(),
+            options: options.clone(),
+        }
+    }
+
+    /// Run the symbol passes, giving every label and variable its address. This must be done before encoding.
+    pub fn resolve_symbols(&mut self, warnings: &mut Warnings) -> Result<(), AssemblerError> {
+        self.symbols = build_symbol_table(&self.lines, &self.options, warnings)?;
+        log::info!("resolved {} symbols", self.symbols.len());
+        Ok(())
+    }
+
+    /// Every symbol the program can refer to, with its address and kind. Empty until the symbols are resolved.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// Translate the program into machine words.
+    pub fn encode(&self) -> Result<Vec<u16>, AssemblerError> {
+        self.to_hack_text().map(|text| to_words(&text))
+    }
+
+    /// Translate the program into `.hack` text, one 16 character word per line with no trailing newline.
+    pub fn to_hack_text(&self) -> Result<String, AssemblerError> {
+        let translated = second_pass(&self.lines, &self.symbols, &self.options)?;
+        // ROM and RAM are separate address spaces, eg @16 is a RAM address whatever is at ROM address 16, which is
+        // easy to lose track of. Show which part of each this program uses.
+        let (base_rom, words) = (self.options.base_rom as usize, translated.lines().count());
+        match words {
+            0 => log::info!("ROM: no instructions"),
+            _ => log::info!("ROM: instructions at {}..{}", base_rom, base_rom + words - 1),
+        }
+        let variables = self.symbols.values().filter(|x| x.kind == SymbolKind::Variable).map(|x| x.address);
+        match (variables.clone().min(), variables.max()) {
+            (Some(first), Some(last)) => log::info!("RAM: variables at {}..{}", first, last),
+            _ => log::info!("RAM: no variables"),
+        }
+        Ok(translated)
+    }
+}
+
+/// Run the symbol passes over a program and return every symbol it can refer to, with its address and kind.
+pub fn resolve_symbols(source: &str, options: &Options, warnings: &mut Warnings) -> Result<SymbolTable, AssemblerError> {
+    let mut program = Program::parse(source, options);
+    program.resolve_symbols(warnings)?;
+    Ok(program.symbols)
+}
+
+/// Encode a single instruction, eg `D=M+1;JEQ`, looking up any symbol in `symbols` rather than allocating it. Spacing
+/// and a trailing comment are allowed as in a program; a label, or a line with no instruction, is an error. Errors are
+/// reported as being on line 1.
+pub fn encode_instruction(line: &str, symbols: &SymbolTable) -> Result<u16, AssemblerError> {
+    let malformed = |reason| AssemblerError::MalformedLine {
+        line: 1,
+        text: line.trim().to_string(),
+        reason,
+    };
+    let instruction = preprocess_line(line).0.ok_or_else(|| malformed("there is no instruction on the line"))?;
+    let parsed_line = parse_line(instruction, 0, 1, false)?;
+    if parsed_line.command_type == CommandKind::LCommand {
+        return Err(malformed("a label is not an instruction"));
+    }
+    let word = translate(&parsed_line, symbols, C_PREFIX)?;
+    Ok(u16::from_str_radix(&word, 2).unwrap())
+}
+
+/// Run the symbol passes over a program and return the address `name` resolves to, or None if the program has no
+/// such symbol.
+pub fn resolve_symbol(source: &str, name: &str) -> Result<Option<u16>, AssemblerError> {
+    let symbol_table = resolve_symbols(source, &Options::default(), &mut Warnings::default())?;
+    Ok(symbol_table.get(name).map(|x| x.address))
+}
+
+/// Parse a program and call `f` with each of its A- and C-instructions in order, eg to collect metrics without
+/// assembling it. If any line fails to parse, its error is returned and `f` is never called.
+pub fn for_each_instruction<F: FnMut(&ParsedLine)>(source: &str, f: F) -> Result<(), AssemblerError> {
+    let parsed_lines = parse_each_line(source, &Options::default());
+    if let Some(error) = parsed_lines.iter().find_map(|x| x.error.clone()) {
+        return Err(error);
+    }
+    parsed_lines
+        .iter()
+        .filter(|x| matches!(x.command_type, CommandKind::ACommand | CommandKind::CCommand))
+        .for_each(f);
+    Ok(())
+}
+
+/// Count a program's instructions, variables, labels, and comment and blank lines, once its symbols are resolved.
+/// Nothing is translated, so an instruction that would fail to translate isn't noticed.
+pub fn count(program: &Program) -> ProgramCounts {
+    let of_kind = |kind| program.symbols.values().filter(|x| x.kind == kind).count();
+    let ignored = |reason| program.lines.iter().filter(|x| x.command_type == CommandKind::ICommand(reason)).count();
+    ProgramCounts {
+        instructions: program
+            .lines
+            .iter()
+            .filter(|x| matches!(x.command_type, CommandKind::ACommand | CommandKind::CCommand | CommandKind::MCommand))
+            .count(),
+        variables: of_kind(SymbolKind::Variable),
+        labels: of_kind(SymbolKind::Label),
+        comments: ignored(IgnoreReason::Comment),
+        blank: ignored(IgnoreReason::Blank),
+    }
+}
+
+/// Count a program's jumps, and how many are conditional, for a rough idea of how complicated its control flow is.
+/// Like `count`, this needs the program's symbols resolved but translates nothing.
+pub fn jump_metrics(program: &Program) -> JumpMetrics {
+    let symbol_table = &program.symbols;
+    let (mut conditional, mut unconditional) = (0, 0);
+    let mut targets = HashSet::new();
+    let mut loaded: Option<u16> = None;
+    for parsed_line in &program.lines {
+        match parsed_line.command_type {
+            CommandKind::ACommand => {
+                let symbol = parsed_line.symbol.as_deref().unwrap();
+                loaded = symbol.parse::<u16>().ok().or_else(|| symbol_table.get(symbol).map(|x| x.address));
+            }
+            CommandKind::CCommand => {
+                match parsed_line.jump.as_deref() {
+                    None => (),
+                    Some("JMP") => unconditional += 1,
+                    Some(_) => conditional += 1,
+                }
+                if parsed_line.jump.is_some() {
+                    targets.extend(loaded);
+                }
+                // A C-instruction that writes A changes the target of any jump after it.
+                if parsed_line.dest.as_deref().unwrap_or_default().contains('A') {
+                    loaded = None;
+                }
+            }
+            CommandKind::LCommand | CommandKind::MCommand | CommandKind::OCommand(_) => loaded = None,
+            CommandKind::ICommand(_) => (),
+        }
+    }
+    JumpMetrics {
+        jumps: conditional + unconditional,
+        conditional,
+        unconditional,
+        targets: targets.len(),
+        branches: conditional + 1,
+    }
+}
+
+/// Assemble a whole program in the symbolic Hack language into its `.hack` text, one 16-bit word per line.
+pub fn assemble(source: &str) -> Result<String, AssemblerError> {
+    assemble_with(source, &Options::default(), &mut Warnings::default())
+}
+
+/// Like `assemble`, but with non-default `Options`, and collecting any warnings into `warnings`.
+pub fn assemble_with(source: &str, options: &Options, warnings: &mut Warnings) -> Result<String, AssemblerError> {
+    // The source is parsed once and the symbol passes only borrow the result, so a large program is held in memory
+    // once rather than copied for each pass. For a 100,000 line program that halves the peak memory use.
+    let mut program = Program::parse(source, options);
+    program.resolve_symbols(warnings)?;
+    program.to_hack_text()
+}
+
+/// Assemble several programs into one ROM image, each placed straight after the one before, sharing one symbol table:
+/// a label defined in one can be jumped to from any other, and a variable is the same RAM address in all of them. A
+/// label may only be defined in one of the programs. Each program comes with a name for warnings to give; an error
+/// comes back with the index of the program it is in. Both have the line number within that program.
+pub fn link(
+    programs: &[(&str, &str)],
+    options: &Options,
+    warnings: &mut Warnings,
+) -> Result<String, (usize, AssemblerError)> {
+    // The programs are assembled as one, joined line by line, with each line number mapped back to the program it
+    // came from afterwards.
+    let mut joined = String::new();
+    let mut first_lines = vec![];
+    for (_, source) in programs {
+        first_lines.push(joined.lines().count() + 1);
+        for line in source.lines() {
+            joined.push_str(line);
+            joined.push('\n');
+        }
+    }
+    let locate = |line: usize| {
+        let program = first_lines.iter().rposition(|x| *x <= line).unwrap();
+        (program, line + 1 - first_lines[program])
+    };
+    let relocate = |mut e: AssemblerError| {
+        let program = e.line_mut().map_or(0, |line| {
+            let (program, local_line) = locate(*line);
+            *line = local_line;
+            program
+        });
+        (program, e)
+    };
+
+    let parsed_lines = parse_each_line(&joined, options);
+    let mut defined_in: HashMap<&str, usize> = HashMap::new();
+    for parsed_line in parsed_lines.iter().filter(|x| x.command_type == CommandKind::LCommand) {
+        let label = parsed_line.symbol.as_deref().unwrap();
+        let (program, _) = locate(parsed_line.source_line);
+        if *defined_in.entry(label).or_insert(program) != program {
+            return Err(relocate(AssemblerError::DuplicateLabel {
+                line: parsed_line.source_line,
+                symbol: label.to_owned(),
+            }));
+        }
+    }
+    let mut found = Warnings::quiet(WarningLevel::All);
+    let symbol_table = build_symbol_table(&parsed_lines, options, &mut found).map_err(relocate)?;
+    for warning in found.warnings() {
+        let (program, line) = locate(warning.line);
+        let message = format!("{} (in {})", warning.message, programs[program].0);
+        warnings.warn(warning.kind, line, message);
+    }
+    second_pass(&parsed_lines, &symbol_table, options).map_err(relocate)
+}
+
+/// Write assembled `.hack` text to any writer, ending every line (the last one included) with a newline.
+pub fn write_binary<W: Write>(w: &mut W, text: &str) -> std::io::Result<()> {
+    for line in text.lines() {
+        w.write_all(line.as_bytes())?;
+        w.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+/// Produce a listing of the program: every source line next to its ROM address and machine code.
+/// With `recover`, instructions that fail to translate are annotated with an `ERROR:` note instead of
+/// aborting, so the rest of the program can still be inspected. The program's symbols must already be resolved, so
+/// an error in resolving them can't be recovered from here.
+pub fn listing(program: &Program, recover: bool) -> Result<String, AssemblerError> {
+    let c_prefix = c_prefix(&program.options);
+    let mut listing = String::new();
+    let mut push_row = |row: String| {
+        listing.push_str(row.trim_end());
+        listing.push('\n');
+    };
+    for (parsed_line, source_text) in program.lines.iter().zip(program.source.lines()) {
+        let source_text = source_text.trim_end();
+        match emitted_words(parsed_line, &program.symbols, &c_prefix) {
+            Ok(words) => {
+                // Padding from a .org gets a row for each of its words, with the directive beside the first.
+                let mut words = words.peekable();
+                if words.peek().is_none() {
+                    push_row(format!("{:>5}  {:16}  {}", "", "", source_text));
+                }
+                for (index, (rom_address, word)) in words.enumerate() {
+                    push_row(format!("{:>5}  {}  {}", rom_address, word, if index == 0 { source_text } else { "" }));
+                }
+            }
+            Err(e) if recover => push_row(format!(
+                "{:>5}  {:16}  {}  ERROR: {}",
+                parsed_line.line_number,
+                "",
+                source_text,
+                e.message()
+            )),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(listing)
+}
+
+/// The number of words in the Hack ROM, the most any program can fill.
+pub const ROM_SIZE: usize = MAX_A_VALUE as usize + 1;
+
+/// The word unused ROM is filled with, whether it is the gap a `.org` leaves or padding out to a fixed size: `NOP`,
+/// with its leading bits replaced by `Options::c_prefix` when that is set.
+pub fn fill_word(options: &Options) -> u16 {
+    u16::from_str_radix(&no_op(&c_prefix(options)), 2).unwrap()
+}
+
+/// Fill assembled `.hack` text out to `size` words with `fill_word`, for loaders that expect a whole ROM image.
+pub fn pad_rom(text: &str, size: usize, options: &Options) -> Result<String, AssemblerError> {
+    pad_lines(text, size, &format!("{:016b}", fill_word(options)))
+}
+
+/// Like `pad_rom`, for the output of `annotate`: each word of padding has a comment too, so `from_annotated` can
+/// still read it back.
+pub fn pad_annotated(annotated: &str, size: usize, options: &Options) -> Result<String, AssemblerError> {
+    let fill = format!("{:016b} // 0, filling the ROM to {} words", fill_word(options), size);
+    pad_lines(annotated, size, &fill)
+}
+
+fn pad_lines(text: &str, size: usize, fill: &str) -> Result<String, AssemblerError> {
+    // Add `fill` lines until there are `size` lines.
+    let words = text.lines().count();
+    if words > size {
+        return Err(AssemblerError::RomOverflow { words, size });
+    }
+    let mut padded = String::with_capacity(size * 17);
+    padded.push_str(text);
+    for _ in words..size {
+        if !padded.is_empty() {
+            padded.push('\n');
+        }
+        padded.push_str(fill);
+    }
+    Ok(padded)
+}
+
+/// The words of assembled `.hack` text as numbers.
+pub fn to_words(text: &str) -> Vec<u16> {
+    text.lines().map(|word| u16::from_str_radix(word, 2).unwrap()).collect()
+}
+
+/// Assemble a program straight to its machine words rather than `.hack` text.
+pub fn assemble_words(source: &str, options: &Options, warnings: &mut Warnings) -> Result<Vec<u16>, AssemblerError> {
+    let mut program = Program::parse(source, options);
+    program.resolve_symbols(warnings)?;
+    program.encode()
+}
+
+/// Assemble a program to its machine words along with a source map, the 1-based source line each word came from,
+/// indexed the same way, eg for a debugger to show the line being run. Padding from a `.org` maps to its line.
+pub fn assemble_with_map(source: &str) -> Result<(Vec<u16>, Vec<usize>), AssemblerError> {
+    let mut program = Program::parse(source, &Options::default());
+    program.resolve_symbols(&mut Warnings::default())?;
+    let words = program.encode()?;
+    let source_map = program
+        .lines
+        .iter()
+        .flat_map(|x| std::iter::repeat_n(x.source_line, x.words()))
+        .collect();
+    Ok((words, source_map))
+}
+
+/// Compare two assembled programs word by word, giving the index of every word that differs along with both versions
+/// of it. Where one program is longer, its extra words are compared against None.
+pub fn diff_words(a: &[u16], b: &[u16]) -> Vec<(usize, Option<u16>, Option<u16>)> {
+    (0..a.len().max(b.len()))
+        .map(|i| (i, a.get(i).copied(), b.get(i).copied()))
+        .filter(|(_, a, b)| a != b)
+        .collect()
+}
+
+/// Rewrite assembled `.hack` text with each word as four hex digits instead of sixteen bits.
+pub fn to_hex(text: &str) -> String {
+    to_words(text)
+        .iter()
+        .map(|word| format!("{:04x}", word))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Convert `.hack` text into C source declaring the words as a `const uint16_t` array called `name`, eight to a line,
+/// and its length as `<name>_len`, for building into a C emulator or firmware.
+pub fn to_c_array(text: &str, name: &str) -> String {
+    let words = to_words(text);
+    let mut c = format!("#include <stddef.h>\n#include <stdint.h>\n\nconst uint16_t {}[] = {{\n", name);
+    for row in words.chunks(8) {
+        let row: Vec<String> = row.iter().map(|word| format!("0x{:04x},", word)).collect();
+        c.push_str(&format!("    {}\n", row.join(" ")));
+    }
+    c.push_str(&format!("}};\nconst size_t {}_len = {};\n", name, words.len()));
+    c
+}
+
+/// Produce a hex dump of the program for comparing against a ROM chip: one `address: word  ; source` line per
+/// instruction, with the ROM address and word in hex. The program's symbols must already be resolved.
+pub fn hexdump(program: &Program) -> Result<String, AssemblerError> {
+    let c_prefix = c_prefix(&program.options);
+    let mut dump = String::new();
+    for (parsed_line, source_text) in program.lines.iter().zip(program.source.lines()) {
+        for (rom_address, word) in emitted_words(parsed_line, &program.symbols, &c_prefix)? {
+            let word = u16::from_str_radix(&word, 2).unwrap();
+            dump.push_str(&format!("{:04x}: {:04x}  ; {}\n", rom_address, word, source_text.trim()));
+        }
+    }
+    Ok(dump)
+}
+
+/// Produce the program as CSV for a spreadsheet: an `address,binary,hex,source` header, then a row per word with its
+/// ROM address in decimal, the word in binary and in hex, and the source line it came from. The program's symbols must
+/// already be resolved.
+pub fn csv(program: &Program) -> Result<String, AssemblerError> {
+    let c_prefix = c_prefix(&program.options);
+    let mut rows = String::from("address,binary,hex,source\n");
+    let mut push_row = |rom_address: usize, word: &str, source_text: &str| {
+        let hex = u16::from_str_radix(word, 2).unwrap();
+        rows.push_str(&format!("{},{},{:04x},{}\n", rom_address, word, hex, csv_field(source_text.trim())));
+    };
+    for (parsed_line, source_text) in program.lines.iter().zip(program.source.lines()) {
+        for (rom_address, word) in emitted_words(parsed_line, &program.symbols, &c_prefix)? {
+            push_row(rom_address, &word, source_text);
+        }
+    }
+    Ok(rows)
+}
+
+fn csv_field(text: &str) -> String {
+    // A field with a comma or quote in it, eg from a comment, is quoted with any quotes inside doubled.
+    if text.contains([',', '"']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+/// Produce `.hack` text with each word followed by a `//` comment giving the instruction the assembler understood it
+/// as, eg `1110111111010010 // D=M+1;JEQ`. This is a review aid: strict emulators won't load a file with comments.
+/// The program's symbols must already be resolved.
+pub fn annotate(program: &Program) -> Result<String, AssemblerError> {
+    let c_prefix = c_prefix(&program.options);
+    let mut annotated = String::new();
+    for parsed_line in &program.lines {
+        let comment = match parsed_line.command_type {
+            CommandKind::OCommand(_) => format!("0, filling up to {}", parsed_line.mnemonic()),
+            _ => parsed_line.mnemonic(),
+        };
+        for (_, word) in emitted_words(parsed_line, &program.symbols, &c_prefix)? {
+            if !annotated.is_empty() {
+                annotated.push('\n');
+            }
+            annotated.push_str(&format!("{} // {}", word, comment));
+        }
+    }
+    Ok(annotated)
+}
+
+/// Rewrite a program in its canonical form, one label or instruction per line with comments and blank lines dropped:
+/// mnemonics in upper case, and comps and dests in the book's order, eg `a+d` becomes `D+A` and `DM=` becomes `MD=`.
+/// Symbols are left as they are. A mnemonic with no canonical form is an error.
+pub fn normalize(source: &str, options: &Options) -> Result<String, AssemblerError> {
+    let mut normalized = String::new();
+    for mut parsed_line in parse_each_line(source, options) {
+        let line = parsed_line.source_line;
+        match parsed_line.command_type {
+            CommandKind::MCommand => return Err(parsed_line.error.unwrap()),
+            CommandKind::ICommand(_) => continue,
+            CommandKind::CCommand => {
+                if let Some(dest) = &parsed_line.dest {
+                    let canonical = canonical_dest(dest).ok_or_else(|| AssemblerError::UnknownDest {
+                        line,
+                        dest: dest.to_owned(),
+                    })?;
+                    parsed_line.dest = Some(canonical.to_string());
+                }
+                let comp = parsed_line.comp.as_ref().ok_or(AssemblerError::MissingComp { line })?;
+                let canonical = canonical_comp(comp).ok_or_else(|| AssemblerError::UnknownComp {
+                    line,
+                    comp: comp.to_owned(),
+                })?;
+                parsed_line.comp = Some(canonical.to_string());
+                if let Some(jump) = &parsed_line.jump {
+                    let canonical = canonical_jump(jump).ok_or_else(|| AssemblerError::UnknownJump {
+                        line,
+                        jump: jump.to_owned(),
+                    })?;
+                    parsed_line.jump = Some(canonical.to_string());
+                }
+            }
+            CommandKind::ACommand | CommandKind::LCommand | CommandKind::OCommand(_) => (),
+        }
+        normalized.push_str(&parsed_line.mnemonic());
+        normalized.push('\n');
+    }
+    Ok(normalized)
+}
+
+/// Explain how the instruction on 1-based source line `line` assembles: its parsed fields, what its symbol resolved
+/// to, and the final word split at its field boundaries. The program's symbols must already be resolved.
+pub fn explain_line(program: &Program, line: usize) -> Result<String, AssemblerError> {
+    let (symbol_table, options) = (&program.symbols, &program.options);
+    let parsed_line = match program.lines.iter().find(|x| x.source_line == line) {
+        Some(parsed_line) => parsed_line.to_owned(),
+        None => return Ok(format!("line {}: no such line in the program\n", line)),
+    };
+    let mut explained = format!("line {}: {}", line, program.source.lines().nth(line - 1).unwrap().trim())
+        .trim_end()
+        .to_string()
+        + "\n";
+    let rom_address = parsed_line.line_number;
+    match parsed_line.command_type {
+        CommandKind::ICommand(_) => explained.push_str("  not an instruction, nothing is emitted\n"),
+        CommandKind::OCommand(padding) => explained.push_str(&format!(
+            "  moves on to ROM address {}, filling {} words from {} with no-ops\n",
+            rom_address as usize + padding,
+            padding,
+            rom_address
+        )),
+        CommandKind::LCommand => explained.push_str(&format!(
+            "  label '{}' for ROM address {}, nothing is emitted\n",
+            parsed_line.symbol.unwrap(),
+            rom_address
+        )),
+        CommandKind::ACommand => {
+            let symbol = parsed_line.symbol.to_owned().unwrap();
+            let word = translate(&parsed_line, symbol_table, &c_prefix(options))?;
+            explained.push_str(&format!("  A-instruction at ROM address {}\n", rom_address));
+            let resolved = match symbol_table.get(&symbol) {
+                Some(entry) => format!("{:?}", entry.kind).to_lowercase() + " at " + &entry.address.to_string(),
+                None => "a number".to_string(),
+            };
+            explained.push_str(&format!("  symbol {:<8} {}\n", symbol, resolved));
+            explained.push_str(&format!("  0 {}\n  = {}\n", &word[1..], word));
+        }
+        CommandKind::CCommand | CommandKind::MCommand => {
+            // Translate first, so each field is known to be in its table by the time it is looked up.
+            let word = translate(&parsed_line, symbol_table, &c_prefix(options))?;
+            let field = |name, mnemonic: &Option<String>, table| {
+                let bits = opcode_bits(table, mnemonic).unwrap();
+                format!("  {:<6} {:<8} {}\n", name, mnemonic.as_deref().unwrap_or("null"), bits)
+            };
+            let fields = field("comp", &parsed_line.comp, &COMP_TABLE[..])
+                + &field("dest", &parsed_line.dest, &DEST_TABLE[..])
+                + &field("jump", &parsed_line.jump, &JUMP_TABLE[..]);
+            explained.push_str(&format!("  C-instruction at ROM address {}\n", rom_address));
+            explained.push_str(&fields);
+            explained.push_str(&format!(
+                "  {} {} {} {} {}\n  = {}\n",
+                &word[..3],
+                &word[3..4],
+                &word[4..10],
+                &word[10..13],
+                &word[13..],
+                word
+            ));
+        }
+    }
+    Ok(explained)
+}
+
+/// Show how each line of a program was parsed, for debugging the parser: its command type, ROM address, and each of
+/// its fields, whether it was set or not. Blank and comment lines are left out. Nothing is resolved or translated, so
+/// a line that failed to parse is shown with its error rather than reported.
+pub fn dump_ast(source: &str, options: &Options) -> String {
+    let mut dumped = String::new();
+    for parsed_line in parse_each_line(source, options) {
+        if let CommandKind::ICommand(_) = parsed_line.command_type {
+            continue;
+        }
+        dumped.push_str(&format!(
+            "line {}, ROM {}: {:?}\n",
+            parsed_line.source_line, parsed_line.line_number, parsed_line.command_type
+        ));
+        for (name, field) in [
+            ("symbol", &parsed_line.symbol),
+            ("dest", &parsed_line.dest),
+            ("comp", &parsed_line.comp),
+            ("jump", &parsed_line.jump),
+        ]
+        .iter()
+        {
+            dumped.push_str(&format!("  {:<6} {:?}\n", name, field));
+        }
+        if let Some(error) = &parsed_line.error {
+            dumped.push_str(&format!("  {:<6} {}\n", "error", error));
+        }
+    }
+    dumped
+}
+
+/// Rebuild assembly from the output of `annotate`, one instruction per word, so it can be assembled again. A
+/// C-instruction is read back from its comment. An A-instruction is read from its word instead, as `@` followed by
+/// the number, since its comment may name a label and the labels themselves aren't in the annotated output.
+pub fn from_annotated(annotated: &str) -> Result<String, AssemblerError> {
+    let mut source = String::new();
+    for (index, line) in annotated.lines().enumerate() {
+        let malformed = |reason| AssemblerError::MalformedLine {
+            line: index + 1,
+            text: line.trim().to_string(),
+            reason,
+        };
+        if line.trim().is_empty() || line.trim().starts_with("//") {
+            // Blank, or a comment such as the build header at the top.
+            continue;
+        }
+        let (word, comment) = line.split_once("//").ok_or_else(|| malformed("the word has no '//' annotation"))?;
+        let word = u16::from_str_radix(word.trim(), 2)
+            .ok()
+            .filter(|_| word.trim().len() == 16)
+            .ok_or_else(|| malformed("an annotated line starts with a 16 bit binary word"))?;
+        let comment = comment.trim();
+        if word <= MAX_A_VALUE {
+            source.push_str(&format!("@{}\n", word));
+        } else if comment.starts_with("0, filling") {
+            // Padding from a .org or pad_annotated, which was a no-op.
+            source.push_str("0\n");
+        } else {
+            source.push_str(comment);
+            source.push('\n');
+        }
+    }
+    Ok(source)
+}
+
+/// The comp, dest, and jump tables the assembler translates with, as a printable cheat sheet.
+pub fn opcode_tables() -> String {
+    let mut tables = String::new();
+    for (title, table) in [
+        ("comp (a cccccc)", &COMP_TABLE[..]),
+        ("dest (ddd)", &DEST_TABLE[..]),
+        ("jump (jjj)", &JUMP_TABLE[..]),
+    ]
+    .iter()
+    {
+        tables.push_str(title);
+        tables.push('\n');
+        for (mnemonic, bits) in table.iter() {
+            tables.push_str(&format!("  {:<6} {}\n", mnemonic.unwrap_or("null"), bits));
+        }
+    }
+    tables
+}
+
+/// The memory layout the assembler assumes, derived from the predefined symbols.
+pub fn memory_map() -> String {
+    let last_register = PREDEFINED_SYMBOLS
+        .iter()
+        .filter(|(name, _)| name.starts_with('R'))
+        .map(|(_, address)| *address)
+        .max()
+        .unwrap();
+    format!(
+        "ROM     0-{}\nRAM     0-{}\n  R0-R{}  0-{}\n  variables from {}\nSCREEN  {}-{}\nKBD     {}\n",
+        MAX_A_VALUE,
+        SCREEN - 1,
+        last_register,
+        last_register,
+        last_register + 1,
+        SCREEN,
+        KBD - 1,
+        KBD
+    )
+}
@@ -0,0 +1,160 @@
+/* Lints are heuristic checks for code that assembles fine but probably doesn't do what was meant. Unlike ordinary
+    warnings they are only run when asked for, as they can be wrong about a correct program. Anything they find is
+    reported into the same Warnings collector.
+*/
+use std::collections::HashSet;
+
+use crate::{CommandKind, ParsedLine, Program, SymbolKind, WarningKind, Warnings, KBD};
+
+/// Run every lint over a program whose symbols are resolved, collecting what they find into `warnings`.
+pub fn lint(program: &Program, warnings: &mut Warnings) {
+    let (parsed_lines, symbol_table) = (&program.lines, &program.symbols);
+
+    // An instruction straight after an unconditional jump can only be reached through a label, so without one in
+    // between it never runs. Only the first instruction of each such stretch is reported.
+    let mut after_jump = false;
+    for parsed_line in parsed_lines {
+        match parsed_line.command_type {
+            CommandKind::LCommand | CommandKind::OCommand(_) => after_jump = false,
+            CommandKind::ACommand | CommandKind::CCommand => {
+                if after_jump {
+                    warnings.warn(
+                        WarningKind::Unreachable,
+                        parsed_line.source_line,
+                        "instruction after an unconditional jump can never run".to_string(),
+                    );
+                }
+                after_jump = parsed_line.jump.as_deref() == Some("JMP");
+            }
+            CommandKind::ICommand(_) | CommandKind::MCommand => (),
+        }
+    }
+
+    // A value loaded into A is wasted if A is loaded again before anything reads it. A C-instruction reads A when
+    // its comp uses A or M, when it stores to M, or when it jumps, as the jump target is A.
+    let mut unused_load: Option<&ParsedLine> = None;
+    for parsed_line in parsed_lines {
+        let overwrites = match parsed_line.command_type {
+            CommandKind::ACommand => true,
+            CommandKind::CCommand => {
+                if parsed_line.comp.as_deref().unwrap_or_default().contains(['A', 'M'])
+                    || parsed_line.dest.as_deref().unwrap_or_default().contains('M')
+                    || parsed_line.jump.is_some()
+                {
+                    unused_load = None;
+                }
+                parsed_line.dest.as_deref().unwrap_or_default().contains('A')
+            }
+            CommandKind::LCommand
+            | CommandKind::ICommand(_)
+            | CommandKind::MCommand
+            | CommandKind::OCommand(_) => false,
+        };
+        if overwrites {
+            if let Some(load) = unused_load {
+                warnings.warn(
+                    WarningKind::UnusedLoad,
+                    load.source_line,
+                    format!("'@{}' is overwritten before it is used", load.symbol.as_deref().unwrap()),
+                );
+            }
+            unused_load = Some(parsed_line).filter(|x| x.command_type == CommandKind::ACommand);
+        }
+    }
+
+    /* Memory-mapped I/O misuse, kept to the two cases that are always wrong:
+        @KBD followed by a C-instruction that stores to M writes the keyboard register, which is read-only.
+        @N for an N above KBD followed by one that reads or stores M uses RAM that doesn't exist.
+    Reading the keyboard or reading and writing the screen are fine, and only the instruction straight after the
+    A-instruction is checked, with no label in between, as otherwise A may hold anything.
+    */
+    let mut loaded: Option<u16> = None;
+    for parsed_line in parsed_lines {
+        match parsed_line.command_type {
+            CommandKind::ACommand => {
+                let symbol = parsed_line.symbol.as_deref().unwrap();
+                loaded = symbol.parse::<u16>().ok().or_else(|| symbol_table.get(symbol).map(|x| x.address));
+            }
+            CommandKind::CCommand => {
+                let reads_m = parsed_line.comp.as_deref().unwrap_or_default().contains('M');
+                let writes_m = parsed_line.dest.as_deref().unwrap_or_default().contains('M');
+                match loaded {
+                    Some(KBD) if writes_m => warnings.warn(
+                        WarningKind::MemoryMap,
+                        parsed_line.source_line,
+                        "stores to the keyboard register, which is read-only".to_string(),
+                    ),
+                    Some(address) if address > KBD && (reads_m || writes_m) => warnings.warn(
+                        WarningKind::MemoryMap,
+                        parsed_line.source_line,
+                        format!("uses RAM address {}, past the last one ({})", address, KBD),
+                    ),
+                    _ => (),
+                }
+                loaded = None;
+            }
+            CommandKind::LCommand | CommandKind::MCommand | CommandKind::OCommand(_) => loaded = None,
+            CommandKind::ICommand(_) => (),
+        }
+    }
+
+    // A label nothing refers to is usually left over from deleted code, or a typo in the @ that was meant for it.
+    let referenced: HashSet<&str> = parsed_lines
+        .iter()
+        .filter(|x| x.command_type == CommandKind::ACommand)
+        .flat_map(|x| x.symbol.as_deref().unwrap().split(['+', '-']))
+        .collect();
+    for parsed_line in parsed_lines {
+        if parsed_line.command_type == CommandKind::LCommand {
+            let label = parsed_line.symbol.as_deref().unwrap();
+            if !referenced.contains(label) {
+                warnings.warn(
+                    WarningKind::UnusedLabel,
+                    parsed_line.source_line,
+                    format!("label '{}' is never referred to", label),
+                );
+            }
+        }
+    }
+
+    /* Variables only ever written or only ever read. This is approximate: a variable counts as written when the
+        instruction straight after one of its @s stores to M, and as read when that instruction's comp uses M. Access
+        through a pointer, or with other instructions in between, isn't seen, so a variable used that way can be
+        reported wrongly, and one used only for its address (eg @x D=A) is never reported.
+    */
+    let mut usage: Vec<(&str, usize, bool, bool)> = vec![];
+    let mut loaded: Option<usize> = None;
+    for parsed_line in parsed_lines {
+        match parsed_line.command_type {
+            CommandKind::ACommand => {
+                let symbol = parsed_line.symbol.as_deref().unwrap();
+                loaded = match symbol_table.get(symbol) {
+                    Some(entry) if entry.kind == SymbolKind::Variable => {
+                        Some(usage.iter().position(|x| x.0 == symbol).unwrap_or_else(|| {
+                            usage.push((symbol, parsed_line.source_line, false, false));
+                            usage.len() - 1
+                        }))
+                    }
+                    _ => None,
+                };
+            }
+            CommandKind::CCommand => {
+                if let Some(index) = loaded {
+                    usage[index].2 |= parsed_line.dest.as_deref().unwrap_or_default().contains('M');
+                    usage[index].3 |= parsed_line.comp.as_deref().unwrap_or_default().contains('M');
+                }
+                loaded = None;
+            }
+            CommandKind::LCommand | CommandKind::MCommand | CommandKind::OCommand(_) => loaded = None,
+            CommandKind::ICommand(_) => (),
+        }
+    }
+    for (variable, line, written, read) in usage {
+        let message = match (written, read) {
+            (true, false) => format!("variable '{}' is written but never read", variable),
+            (false, true) => format!("variable '{}' is read but never written", variable),
+            _ => continue,
+        };
+        warnings.warn(WarningKind::VariableUse, line, message);
+    }
+}
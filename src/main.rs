@@ -1,5 +1,20 @@
-use std::{collections::HashMap, convert::TryInto, fs::File, io::Write, path::PathBuf};
-use structopt::StructOpt;
+use hack_assembler::{
+    annotate, assemble_with, assemble_words, check_fixture_dir, count, csv, diff_words, dump_ast, explain_line,
+    fill_word, format_symbol_file, from_annotated, hexdump, jump_metrics, link, lint, listing, memory_map, normalize,
+    opcode_tables, pad_annotated, pad_rom, parse_symbol_file, preprocess, resolve_symbols, self_test, symbol_mismatches,
+    to_c_array, to_hex, to_words, write_binary, AssemblerError, Options, Program, WarningLevel, Warnings, ROM_SIZE,
+};
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc,
+    time::{Duration, Instant, SystemTime},
+};
+use notify::{RecursiveMode, Watcher};
+use structopt::{clap::AppSettings, StructOpt};
 
 /* A minimal assembler for the Hack computer from Nand2Tetris.
     Takes an input file "abc.asm" in the symbolic Hack machine language and writes the corrosponding binary
@@ -9,509 +24,1233 @@ use structopt::StructOpt;
     https://www.nand2tetris.org/project06
 */
 #[derive(Debug, StructOpt)]
-// StructOpt crate for command line argument parsing (only the path of input file for now).
+// StructOpt crate for command line argument parsing.
+#[structopt(setting = AppSettings::SubcommandsNegateReqs)]
 struct Cli {
-    #[structopt(parse(from_os_str))]
-    path: std::path::PathBuf,
-}
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
-enum CommandKind {
-    ACommand,
-    CCommand,
-    LCommand,
-    ICommand, // An invalid command, returned upon encountering a line that is not an instruction. Ignored completely.
-}
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
-struct ParsedLine {
-    // The "parts" of an instruction. CommandKind is either A, C, or L(abel).
-    // Depending on the CommandKind, Dest, Comp, and Jump are either Some(value) or None
-    // Examples:
-    // @100
-    // command_type: A_Command, symbol: Some("100"), dest: None, Comp: None, Jump: None
-    // D = M + 1;JEQ
-    // command_type: C_Command, symbol: None, dest: Some("D"), comp: Some("M + 1"), jump: Some("JEQ")
-    // (ORANGE)
-    // comamand_type: L_Command, symbol: Some("ORANGE"), ... : None
-    command_type: CommandKind,
-    symbol: Option<String>,
-    dest: Option<String>,
-    comp: Option<String>,
-    jump: Option<String>,
-    line_number: isize,
-}
-
-fn parse_line(line: String, line_number: isize) -> ParsedLine {
-    // Assumes the line has been preprocessed already. That means all comments and whitespace have been removed and the
-    // line is not a comment. Therefore, everything to parse is a valid Hack Assembly Language command of some form.
-
-    let mut ct = CommandKind::ACommand;
-    let mut sym: Option<String> = None;
-    let mut des: Option<String> = None;
-    let mut temp_comp: String = "".to_string(); // Our "builder" for comp as it may have multiple parts.
-    let mut com: Option<String> = None;
-    let mut jmp: Option<String> = None;
-
-    let line_chars = line.chars();
-    for char in line_chars {
-        if char == '@' {
-            // A instruction, take everything until EOL into the vector.
-            if line.chars().nth(1).unwrap().is_numeric() {
-                // We're an a instruction with a valid number, not a label.
-                ct = CommandKind::ACommand;
-                sym = Some(line.chars().skip(1).collect::<String>());
-                break; // we're done with this line.
-            } else {
-                // We a label for a variable ala @dog
-                ct = CommandKind::ACommand;
-                sym = Some(line.chars().skip(1).collect::<String>());
-                break;
-            }
-        } else if char == '(' {
-            // We're a nice LOOP label of form (XXX); take just the XXX
-            ct = CommandKind::LCommand;
-            sym = Some(
-                line.chars()
-                    .skip(1)
-                    .take_while(|x| x != &')')
-                    .collect::<String>(),
-            );
-            break;
-        } else {
-            ct = CommandKind::CCommand;
-            if char == '=' {
-                // There's an assignment of some form
-                let pos_of_eq = line.chars().position(|x| x == '=').unwrap();
-                des = Some(line.chars().take(pos_of_eq).collect::<String>()); // Collect up to the = sign.
-                &temp_comp.push(line.chars().nth(pos_of_eq + 1).unwrap());
-            } else if char == '+' || char == '&' || char == '|' {
-                // '-' has to be handled in its own case as it could be a unary operator.
-                if line.contains("=") {
-                    // The character is an operator and has two arguments, but the first argument was found above.
-                    let pos_of_op = line
-                        .chars()
-                        .position(|x| x == '+' || x == '&' || x == '|')
-                        .unwrap();
-                    &temp_comp.push_str(
-                        line.chars()
-                            .skip(pos_of_op)
-                            .take(2)
-                            .collect::<String>()
-                            .as_str(),
-                    );
-                } else {
-                    // There's no destination but we still have some operation going on, eg D+M
-                    // In this case, we won't have handled the first part of the comparison yet.
-                    let pos_of_op = line
-                        .chars()
-                        .position(|x| x == '+' || x == '&' || x == '|')
-                        .unwrap();
-                    &temp_comp.push_str(
-                        line.chars()
-                            .skip(pos_of_op - 1)
-                            .take(3)
-                            .collect::<String>()
-                            .as_str(),
-                    );
-                }
-            } else if char == '-' || char == '!' {
-                // -X, !X, D=-X, D=!X, D=M-X, M-X
-                // '-' Could be a unary or binary operator, so we must check.
-                let pos_of_op = line.chars().position(|x| x == '-' || x == '!').unwrap();
-                if pos_of_op != 0 && char == '-' {
-                    // Could still be unary but we have a destination
-                    if line.chars().nth(pos_of_op - 1).unwrap() == '=' {
-                        // Unary (char after '=' will be caught by = case)
-                        &temp_comp.push(line.chars().nth(pos_of_op + 1).unwrap());
-                    } else {
-                        // Binary
-                        if line.contains("=") {
-                            // Don't capture the character after "=" again.
-                            &temp_comp.push_str(
-                                line.chars()
-                                    .skip(pos_of_op)
-                                    .take(2)
-                                    .collect::<String>()
-                                    .as_str(),
-                            );
-                        } else {
-                            &temp_comp.push_str(
-                                line.chars()
-                                    .skip(pos_of_op - 1)
-                                    .take(3)
-                                    .collect::<String>()
-                                    .as_str(),
-                            );
-                        }
-                    }
-                } else if pos_of_op == 0 {
-                    // We're at the start and are - or !
-                    &temp_comp.push_str(line.chars().take(2).collect::<String>().as_str());
-                } else if char == '!' {
-                    // We're a ! instruction and not at the beginning, eg D=!M; the '!' will be caught by '=' case.
-                    &temp_comp.push(line.chars().nth(pos_of_op + 1).unwrap());
-                }
-            } else if char == ';' {
-                // Character represents a JMP instruction is to follow
-                if line.contains("=") {
-                    // We'll have parsed the destination and the comp already; just take care of jump.
-                    let i = line.chars().position(|x| x == ';').unwrap();
-                    jmp = Some(line.chars().skip(i + 1).take(3).collect::<String>());
-                } else {
-                    // There is no destination, so we may or may not have parsed the comp.
-                    if line.contains(|x| x == '+' || x == '-' || x == '&' || x == '|') {
-                        // We'll have parsed the operation and operator. Just parse the Jump.
-                        let i = line.chars().position(|x| x == ';').unwrap();
-                        jmp = Some(line.chars().skip(i + 1).take(3).collect::<String>());
-                    } else {
-                        // There's no destination, and no operation; comp is just a register or memory.
-                        // In this case, we need to store the comparison register as well as process the jump.
-                        let i = line.chars().position(|x| x == ';').unwrap();
-                        let com = line.chars().nth(i - 1).unwrap();
-                        temp_comp.push(com);
-                        jmp = Some(line.chars().skip(i + 1).take(3).collect::<String>());
-                    }
-                }
-            }
+    #[structopt(subcommand)]
+    command: Option<Command>,
+    #[structopt(parse(from_os_str), required_unless_one = &["list-comps", "memory-map", "dir", "link"])]
+    path: Option<std::path::PathBuf>,
+    /// Assemble every .asm file in this directory instead of a single file.
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["path", "watch"])]
+    dir: Option<PathBuf>,
+    /// Link these .asm files into one ROM image, written to the first one's .hack file. Each is placed after the one
+    /// before and they share their labels and variables, so one can jump to a label defined in another. Only plain
+    /// binary output is written, so the options for other files and formats can't be given with it.
+    #[structopt(
+        long,
+        parse(from_os_str),
+        min_values = 2,
+        conflicts_with_all = &[
+            "path", "dir", "watch", "bundle", "emit", "listing", "recover", "format", "array-name", "annotate",
+            "header", "rom-size", "bank-size", "dump-rom-image", "clipboard", "checksum", "checksum-file",
+            "expect-symbols", "lint", "count-only", "metrics", "normalize-only", "from-annotated", "explain-line",
+            "dump-ast",
+        ]
+    )]
+    link: Vec<PathBuf>,
+    /// Which files to write, comma separated: bin ("abc.hack"), listing ("abc.lst", each source line next to its ROM
+    /// address and machine code), and sym ("abc.sym", the program's symbols and their addresses).
+    #[structopt(long, use_delimiter = true, number_of_values = 1, default_value = "bin")]
+    emit: Vec<Artifact>,
+    /// How to write the machine code: binary (one word per line in "abc.hack"), hex (one word per line in "abc.hex"),
+    /// hexdump ("abc.dump", each word next to its ROM address and source line), csv ("abc.csv", a row per word with
+    /// its address, binary, hex, and source line), or c-array ("abc.h", a C array of the words).
+    #[structopt(long, default_value = "binary")]
+    format: Format,
+    /// The name of the array --format c-array declares. Its length is declared as the name followed by "_len".
+    #[structopt(long, default_value = "rom", parse(try_from_str = parse_array_name))]
+    array_name: String,
+    /// With --format binary, follow each word with a // comment giving the instruction it was assembled from, eg
+    /// "1110111111010010 // D=M+1;JEQ". This is for reviewing the output by eye: emulators won't load the file.
+    #[structopt(long)]
+    annotate: bool,
+    /// Start the listing, the csv, and --annotate output with "//" comments saying which build produced them: the
+    /// assembler version, the source file, when it was built, and its number of instructions. SOURCE_DATE_EPOCH, if
+    /// set, is used as the time. Plain .hack output never has a header, as emulators won't load it.
+    #[structopt(long)]
+    header: bool,
+    /// Don't end the last line of the output files with a newline.
+    #[structopt(long)]
+    no_trailing_newline: bool,
+    /// How many words of ROM the machine code fills: exact (one per instruction) or a number of words, up to 32768,
+    /// with the rest filled with no-ops.
+    #[structopt(long, default_value = "exact")]
+    rom_size: RomSize,
+    /// Split the machine code across files of up to this many words each, "abc.0.hack", "abc.1.hack" and so on, for
+    /// hardware with banked ROM. Only binary and hex output can be split.
+    #[structopt(long, conflicts_with_all = &["annotate", "clipboard", "checksum", "checksum-file"])]
+    bank_size: Option<usize>,
+    /// Fill the last bank out to --bank-size with no-ops.
+    #[structopt(long, requires = "bank-size")]
+    pad_banks: bool,
+    /// Also write the machine code to this file as a raw ROM image, two bytes per word.
+    #[structopt(long, parse(from_os_str))]
+    dump_rom_image: Option<PathBuf>,
+    /// The byte order of the words in the ROM image: big or little.
+    #[structopt(long, default_value = "big")]
+    endian: Endian,
+    /// Also copy the machine code output to the system clipboard, eg to paste into the web emulator. Only available
+    /// when built with the "clipboard" feature.
+    #[structopt(long)]
+    clipboard: bool,
+    /// Print the CRC32 checksum of the machine code output to stderr.
+    #[structopt(long)]
+    checksum: bool,
+    /// Write the CRC32 checksum of the machine code output to this file.
+    #[structopt(long, parse(from_os_str))]
+    checksum_file: Option<PathBuf>,
+    /// Instead of writing each .hack file on its own, put them all in this zip archive, eg for handing in a project
+    /// assembled with --dir. Nothing is written if any file fails to assemble.
+    #[structopt(long, parse(from_os_str), conflicts_with = "watch")]
+    bundle: Option<PathBuf>,
+    /// Log more about what the assembler is doing: -v for progress, -vv for each symbol, -vvv for each instruction.
+    /// RUST_LOG, if set, takes precedence.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Print nothing but errors: no warnings, progress, or summaries. It overrides -v and RUST_LOG, eg for a Makefile
+    /// that should stay silent unless something fails.
+    #[structopt(short, long, conflicts_with = "watch")]
+    quiet: bool,
+    /// Also write the listing; the same as adding it to --emit.
+    #[structopt(long)]
+    listing: bool,
+    /// Annotate bad lines in the listing instead of stopping at the first one. Only an instruction that fails to
+    /// encode is recovered from: an error resolving the program's symbols, eg an undefined name under --require-decl,
+    /// still stops the listing. It needs --listing or --emit listing.
+    #[structopt(long)]
+    recover: bool,
+    /// Print the comp, dest, and jump tables the assembler uses and exit.
+    #[structopt(long)]
+    list_comps: bool,
+    /// Print the memory layout the assembler assumes (RAM, SCREEN, KBD, ROM) and exit.
+    #[structopt(long)]
+    memory_map: bool,
+    /// Print how many instructions, variables, labels, comment lines, and blank lines the program has and exit,
+    /// without writing any files.
+    #[structopt(long, conflicts_with_all = &["dir", "watch"])]
+    count_only: bool,
+    /// Print how many jumps the program has, conditional and unconditional, how many places they go to, and its
+    /// branch count (the conditional jumps plus one), and exit.
+    #[structopt(long, conflicts_with_all = &["dir", "watch", "count-only"])]
+    metrics: bool,
+    /// Print the program in canonical form and exit, without translating it: one label or instruction per line, with
+    /// upper case mnemonics and comps and dests in the book's order. Comments and blank lines are dropped.
+    #[structopt(long, conflicts_with_all = &["dir", "watch"])]
+    normalize_only: bool,
+    /// Read the input as the output of --annotate, and print the program rebuilt from it and exit, eg to assemble
+    /// again after editing the annotations.
+    #[structopt(long, conflicts_with_all = &["dir", "watch", "count-only", "normalize-only"])]
+    from_annotated: bool,
+    /// Print how the instruction on this source line is assembled, field by field, and exit.
+    #[structopt(long, conflicts_with_all = &["dir", "watch", "count-only"])]
+    explain_line: Option<usize>,
+    /// Print how each line of the program was parsed, field by field, and exit. For debugging the parser.
+    #[structopt(long, conflicts_with_all = &["dir", "watch", "count-only", "explain-line"])]
+    dump_ast: bool,
+    /// Seed the symbol table with fixed "name=address" pairs read from this file.
+    #[structopt(long, parse(from_os_str))]
+    symbols_in: Option<PathBuf>,
+    /// Define a constant, eg --define WIDTH=32, so the same program can be assembled with different values. Its value
+    /// is only a number, so unlike --symbols-in it doesn't move where variables go. It takes precedence over
+    /// --symbols-in, and a later --define of the same name over an earlier one.
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_define))]
+    define: Vec<(String, u16)>,
+    /// Fail unless each symbol in this file, in the --symbols-in format, is at the address given, eg to check a label
+    /// or variable landed where an assignment says it must.
+    #[structopt(long, parse(from_os_str))]
+    expect_symbols: Option<PathBuf>,
+    /// Fail if a variable is allocated a RAM address above this one.
+    #[structopt(long)]
+    max_var_addr: Option<u16>,
+    /// Reject an instruction longer than this many characters, not counting whitespace, as malformed.
+    #[structopt(long, default_value = "1024")]
+    max_line_len: usize,
+    /// Which warnings to report: none, default, or all.
+    #[structopt(short = "W", long = "warnings", default_value = "default")]
+    warning_level: WarningLevel,
+    /// Also run the lints, heuristic checks for code that is probably a mistake, eg unreachable instructions or
+    /// an A-instruction whose value is never used.
+    #[structopt(long)]
+    lint: bool,
+    /// Treat a line holding only a jump mnemonic, eg "JMP", as "0;JMP".
+    #[structopt(long)]
+    jmp_shorthand: bool,
+    /// Make symbols starting with "." local to the label before them, so each function can have its own "(.loop)".
+    #[structopt(long)]
+    local_labels: bool,
+    /// Don't predefine R0-R15, SP, LCL, ARG, THIS, THAT, SCREEN, or KBD; the program must define every symbol itself.
+    #[structopt(long)]
+    no_predefined: bool,
+    /// Fail on a symbol that isn't a label, predefined, or from --symbols-in, instead of making it a variable.
+    #[structopt(long)]
+    require_decl: bool,
+    /// Reject labels starting with "__", a prefix kept for names the assembler generates itself, and make passing in
+    /// a .hack file an error rather than a warning.
+    #[structopt(long)]
+    strict: bool,
+    /// Allow A-instructions that add and subtract numbers and constants, eg @SCREEN+32. Labels, predefined symbols
+    /// and those from --symbols-in can be used, but not variables.
+    #[structopt(long)]
+    expr: bool,
+    /// Warn about symbols named like a register or mnemonic, eg @D or (JMP), which are easily misread.
+    #[structopt(long)]
+    warn_confusable: bool,
+    /// Require labels to start in the first column and instructions to be indented.
+    #[structopt(long)]
+    column_strict: bool,
+    /// Reject a line with anything after its instruction, eg "@i j", instead of removing the space and reading "@ij",
+    /// or "(LOOP) x", instead of ignoring the "x".
+    #[structopt(long)]
+    strict_eol: bool,
+    /// Check that the output has exactly one word per instruction, failing if not. Only a bug in the assembler could
+    /// make them differ.
+    #[structopt(long)]
+    verify_count: bool,
+    /// Skip symbol resolution for machine generated code where every A-instruction is a number and there are no
+    /// labels; any symbol is an error.
+    #[structopt(long)]
+    assume_resolved: bool,
+    /// The ROM address the program will be loaded at; every label is offset by it.
+    #[structopt(long, default_value = "0")]
+    base_rom: u16,
+    /// Start C-instructions with these three bits instead of 111, for an extended Hack CPU.
+    #[structopt(long, parse(try_from_str = parse_c_prefix))]
+    c_prefix: Option<u8>,
+    /// When to color error messages: auto, always, or never. Auto colors a terminal unless NO_COLOR is set.
+    #[structopt(long, default_value = "auto")]
+    color: ColorChoice,
+    /// How to print errors: human, or json for editors, as an array of {file, line, column, span, code, message}
+    /// objects. With json, --dir prints only the errors, without a line per file or the summary.
+    #[structopt(long, default_value = "human")]
+    error_format: ErrorFormat,
+    /// Keep running, and assemble the program again every time it (or the --symbols-in file) is saved.
+    #[structopt(long)]
+    watch: bool,
+}
+
+fn parse_define(define: &str) -> Result<(String, u16), String> {
+    // The same NAME=VALUE form as a line of a --symbols-in file.
+    match parse_symbol_file(define).map_err(|e| e.message())?.as_slice() {
+        [entry] => Ok(entry.to_owned()),
+        _ => Err(format!("'{}' is not a NAME=VALUE definition", define)),
+    }
+}
+
+fn parse_array_name(name: &str) -> Result<String, String> {
+    // A C identifier, so the output compiles.
+    let starts_well = name.chars().next().is_some_and(|x| x.is_ascii_alphabetic() || x == '_');
+    if !starts_well || !name.chars().all(|x| x.is_ascii_alphanumeric() || x == '_') {
+        return Err(format!("'{}' is not a C identifier", name));
+    }
+    Ok(name.to_string())
+}
+
+fn parse_c_prefix(bits: &str) -> Result<u8, String> {
+    // Exactly three binary digits, so the C-instruction stays 16 bits wide.
+    if bits.len() != 3 {
+        return Err(format!("'{}' is not 3 bits long", bits));
+    }
+    if !bits.chars().all(|x| x == '0' || x == '1') {
+        return Err(format!("'{}' is not made of binary digits", bits));
+    }
+    Ok(u8::from_str_radix(bits, 2).unwrap())
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Assemble the example programs built into the binary and check the output against the known-good results.
+    SelfTest {
+        /// Instead, check every .asm file in this directory against the .hack file beside it.
+        #[structopt(long, parse(from_os_str))]
+        fixtures: Option<PathBuf>,
+    },
+    /// Assemble two programs and show the ROM addresses where their machine code differs, eg to check that a refactor
+    /// didn't change what a program does. Exits with status 1 if there are any differences.
+    Diff {
+        #[structopt(parse(from_os_str))]
+        a: PathBuf,
+        #[structopt(parse(from_os_str))]
+        b: PathBuf,
+    },
+    /// Read instructions from stdin one line at a time, printing each one's machine code straight away. Labels and
+    /// variables are remembered from line to line; ".symbols" lists them and ".quit" exits.
+    Repl,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Artifact {
+    Bin,
+    Listing,
+    Sym,
+}
+
+impl FromStr for Artifact {
+    type Err = String;
+
+    fn from_str(artifact: &str) -> Result<Self, Self::Err> {
+        match artifact {
+            "bin" => Ok(Artifact::Bin),
+            "listing" => Ok(Artifact::Listing),
+            "sym" => Ok(Artifact::Sym),
+            _ => Err(format!("unknown artifact '{}', expected bin, listing, or sym", artifact)),
         }
     }
-    if !temp_comp.is_empty() {
-        com = Some(temp_comp.to_string());
-    }
-
-    ParsedLine {
-        command_type: ct,
-        symbol: sym,
-        dest: des,
-        comp: com,
-        jump: jmp,
-        line_number,
-    }
-}
-
-/// Return the contents of the supplied file as a String, or panic.
-fn get_file_contents(asm_file: &PathBuf) -> String {
-    match std::fs::read_to_string(asm_file) {
-        Ok(r) => r,
-        Err(e) => panic!("Couldn't read from file! Error: {}", e),
-    }
-}
-
-fn preprocess_line(line: String) -> Option<String> {
-    // Strip comments, whitespaces, and spaces between words from each line.
-    /* Lines can be comments, empty, an instruction or label, or a combo of an instruction and comment.
-    Examples:
-    // File: add.asm
-    // adds 100 to whatever's at register 300 and stores it at register 100
-    @300
-    D = M
-    @100
-    M = D + A
-
-    The above should become:
-    @300
-    D=M
-    @100
-    M=D+A
-     */
-    let line_trimmed = line.trim();
-    if line_trimmed.contains("//") {
-        // We have a comment somewhere
-        if line_trimmed.starts_with("//") {
-            None
-        } else {
-            let comment_start_index = line_trimmed.chars().position(|x| x == '/').unwrap();
-            let line_nocomment = line_trimmed
-                .chars()
-                .take(comment_start_index - 1)
-                .collect::<String>();
-            Some(line_nocomment.split_whitespace().collect::<String>())
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Binary,
+    Hex,
+    Hexdump,
+    Csv,
+    CArray,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "binary" => Ok(Format::Binary),
+            "hex" => Ok(Format::Hex),
+            "hexdump" => Ok(Format::Hexdump),
+            "csv" => Ok(Format::Csv),
+            "c-array" => Ok(Format::CArray),
+            _ => Err(format!("unknown format '{}', expected binary, hex, hexdump, csv, or c-array", format)),
         }
-    } else {
-        let potential_instruction = line.split_whitespace().collect::<String>();
-        if potential_instruction.is_empty() {
-            None
-        } else {
-            Some(potential_instruction)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RomSize {
+    Exact,
+    Words(usize),
+}
+
+impl FromStr for RomSize {
+    type Err = String;
+
+    fn from_str(size: &str) -> Result<Self, Self::Err> {
+        match size {
+            "exact" => Ok(RomSize::Exact),
+            _ => match size.parse::<usize>() {
+                Ok(words) if (1..=ROM_SIZE).contains(&words) => Ok(RomSize::Words(words)),
+                _ => Err(format!("unknown ROM size '{}', expected exact or 1 to {}", size, ROM_SIZE)),
+            },
         }
     }
 }
 
-fn parse_each_line(contents: String) -> Vec<ParsedLine> {
-    // Parse each line and return a vector containing them all, to avoid reparsing the document later on.
-    // If we have an L_command we need to decrement the line-number
-    let mut parsed_lines: Vec<ParsedLine> = vec![];
-    let mut line_number = -1;
-    for line in contents.lines() {
-        let preproc_line = match preprocess_line(line.to_string()) {
-            Some(instr) => {line_number += 1; parse_line(instr, line_number.try_into().unwrap())},
-            None => ParsedLine {command_type: CommandKind::ICommand, symbol: None, dest: None, comp: None, jump: None, line_number: 0}
-        };
-        if preproc_line.command_type == CommandKind::LCommand {
-            line_number -= 1;
+#[derive(Debug, Clone, Copy)]
+enum Endian {
+    Big,
+    Little,
+}
+
+impl FromStr for Endian {
+    type Err = String;
+
+    fn from_str(endian: &str) -> Result<Self, Self::Err> {
+        match endian {
+            "big" => Ok(Endian::Big),
+            "little" => Ok(Endian::Little),
+            _ => Err(format!("unknown byte order '{}', expected big or little", endian)),
         }
-        parsed_lines.push(preproc_line);
     }
-    parsed_lines
 }
 
-fn translate(instruction: ParsedLine, symbol_table: HashMap<Option<String>, String>) -> String {
-    /* Translate the parsed content into their corrosponding binary instructions.
-    Each piece of ParsedLine (except LCommands, which are special) has one and only one binary representation.
-    A instructions are just translated into the binary representation of their symbol, with a leading 0 eg:
-        @2 --> 0000000000000010
-    LCommands have a non-number value as their symbol and require consulting a symbol table we populated earlier.
-        @dog --> | dog | 16 | --> 0000000000010000
-    C instructions have multiple parts, one per field with three leading 1s:
-        D=A+1;JMP ->  111accccccdddjjj where acccccc are determined by the comp, ddd by dest, and jjj by jump.
-    */
-    let dest_map: HashMap<Option<String>, &str> = [
-        (None, "000"),
-        (Some("M".to_string()), "001"),
-        (Some("D".to_string()), "010"),
-        (Some("MD".to_string()), "011"),
-        (Some("A".to_string()), "100"),
-        (Some("AM".to_string()), "101"),
-        (Some("AD".to_string()), "110"),
-        (Some("AMD".to_string()), "111"),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    let jump_map: HashMap<Option<String>, &str> = [
-        (None, "000"),
-        (Some("JGT".to_string()), "001"),
-        (Some("JEQ".to_string()), "010"),
-        (Some("JGE".to_string()), "011"),
-        (Some("JLT".to_string()), "100"),
-        (Some("JNE".to_string()), "101"),
-        (Some("JLE".to_string()), "110"),
-        (Some("JMP".to_string()), "111"),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    let comp_map: HashMap<Option<String>, &str> = [
-        (Some("0".to_string()), "0101010"),
-        (Some("1".to_string()), "0111111"),
-        (Some("-1".to_string()), "0111010"),
-        (Some("D".to_string()), "0001100"),
-        (Some("A".to_string()), "0110000"),
-        (Some("M".to_string()), "1110000"),
-        (Some("!D".to_string()), "0001101"),
-        (Some("!A".to_string()), "0110001"),
-        (Some("!M".to_string()), "1110001"),
-        (Some("-D".to_string()), "0001111"),
-        (Some("-A".to_string()), "0110011"),
-        (Some("-M".to_string()), "1110011"),
-        (Some("D+1".to_string()), "0011111"),
-        (Some("A+1".to_string()), "0110111"),
-        (Some("M+1".to_string()), "1110111"),
-        (Some("D-1".to_string()), "0001110"),
-        (Some("A-1".to_string()), "0110010"),
-        (Some("M-1".to_string()), "1110010"),
-        (Some("D+A".to_string()), "0000010"),
-        (Some("D+M".to_string()), "1000010"),
-        (Some("D-A".to_string()), "0010011"),
-        (Some("D-M".to_string()), "1010011"),
-        (Some("A-D".to_string()), "0000111"),
-        (Some("M-D".to_string()), "1000111"),
-        (Some("D&A".to_string()), "0000000"),
-        (Some("D&M".to_string()), "1000000"),
-        (Some("D|A".to_string()), "0010101"),
-        (Some("D|M".to_string()), "1010101"),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    if instruction.command_type == CommandKind::ACommand {
-        if instruction
-            .symbol
-            .to_owned()
-            .unwrap()
-            .chars()
-            .nth(0)
-            .unwrap()
-            .is_numeric()
-        {
-            let addr_as_binstr =
-                format!("{:b}", instruction.symbol.unwrap().parse::<u16>().unwrap());
-            let mut final_addr_binstr = addr_as_binstr.to_owned();
-            // Pad the ouput with enough zeros to "become" a 16-bit word.
-            for _ in 0..(16 - addr_as_binstr.len()) {
-                final_addr_binstr.insert(0, '0');
-            }
-            final_addr_binstr
-        } else {
-            // We're not numeric, so we're some sort of label (eg @cat)
-            let symbol_from_table = symbol_table.get(&instruction.symbol).unwrap();
-            let addr_as_binstr = format!("{:b}", symbol_from_table.parse::<u16>().unwrap());
-            let mut final_addr_binstr = addr_as_binstr.to_owned();
-            for _ in 0..(16 - addr_as_binstr.len()) {
-                final_addr_binstr.insert(0, '0');
+#[derive(Debug, Clone, Copy)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(choice: &str) -> Result<Self, Self::Err> {
+        match choice {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!("unknown color choice '{}', expected auto, always, or never", choice)),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum ErrorFormat {
+    Human,
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> Result<Self, Self::Err> {
+        match format {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown error format '{}', expected human or json", format)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ErrorStyle {
+    color: bool,
+    json: bool,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        // Errors go to stderr, so that's the stream that has to be a terminal. See https://no-color.org/.
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").is_none_or(|x| x.is_empty()) && atty::is(atty::Stream::Stderr)
             }
-            final_addr_binstr
         }
+    }
+}
+
+fn output_bytes(to_write: &str, trailing_newline: bool) -> Vec<u8> {
+    // Some ROM loaders reject a newline after the last word, so it can be left off.
+    let mut bytes = Vec::with_capacity(to_write.len() + 1);
+    if trailing_newline {
+        write_binary(&mut bytes, to_write).unwrap();
     } else {
-        // We're a C instruction. The word is 111accccccdddjjj:
-        let comp_bits = comp_map.get(&instruction.comp).unwrap();
-        let dest_bits = dest_map.get(&instruction.dest).unwrap();
-        let jump_bits = jump_map.get(&instruction.jump).unwrap();
-        "111".to_string() + comp_bits + dest_bits + jump_bits
-    }
-}
-
-fn populate_symbol_table(
-    label: ParsedLine,
-    mut table: HashMap<Option<String>, String>,
-    last_address: isize,
-) -> (HashMap<Option<String>, String>, isize) {
-    // Takes a command and writes the label to the first free address in memory.
-    // The Hack language uses 0-15 as pre-set symbols; any other command will be allocated at 16 or higher until we
-    // hit the screen address - 1, at which point we're out of space.
-    // Unless the label is a loop declaration (XXX), which needs to be mapped to the line number it occurs on.
-
-    let mut address_to_assign = last_address;
-    if table.contains_key(&label.symbol) && label.command_type != CommandKind::LCommand {
-        (table, last_address)
+        bytes.extend_from_slice(to_write.lines().collect::<Vec<&str>>().join("\n").as_bytes());
+    }
+    bytes
+}
+
+fn write_output(path: &Path, to_write: &[u8], style: ErrorStyle) -> Result<(), String> {
+    // Write an output file, rendering a failure like any other error.
+    write_binary_to_file(path, to_write).map_err(|e| render_file_problem(path, "write-failed", &e.to_string(), style))
+}
+
+fn write_binary_to_file(path: &Path, to_write: &[u8]) -> std::io::Result<()> {
+    /* The whole file is built in memory first, so it is written in one go rather than one small line at a time.
+        It goes to a temporary file beside the real one, which is renamed into place only once everything is on disk,
+        so a failure part way through (eg a full disk) leaves the old output, or none, rather than a truncated one.
+    */
+    let filename = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "the path has no file name"))?;
+    let mut temporary = OsString::from(".");
+    temporary.push(filename);
+    temporary.push(".tmp");
+    let temporary = path.with_file_name(temporary);
+    let written = File::create(&temporary).and_then(|mut file| {
+        file.write_all(to_write)?;
+        file.sync_all()
+    });
+    match written.and_then(|_| std::fs::rename(&temporary, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temporary);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_to_clipboard(text: &str) -> Result<(), String> {
+    // Fails with the platform's reason when there's no clipboard to copy to, eg over ssh with no display.
+    arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.set_text(text))
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_to_clipboard(_text: &str) -> Result<(), String> {
+    Err("this build has no clipboard support, rebuild with --features clipboard".to_string())
+}
+
+fn read_source(path: &Path, style: ErrorStyle) -> Result<String, String> {
+    // Read a program, decompressing it first if it was gzipped, which is told by a .gz extension or the gzip magic
+    // number at the start.
+    let read_error = |e: std::io::Error| render_file_problem(path, "read-failed", &e.to_string(), style);
+    if path.is_dir() {
+        let problem = "is a directory, expected a .asm file (--dir assembles every file in one)";
+        return Err(render_file_problem(path, "is-a-directory", problem, style));
+    }
+    let bytes = std::fs::read(path).map_err(read_error)?;
+    if path.extension().is_some_and(|x| x == "gz") || bytes.starts_with(&[0x1f, 0x8b]) {
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(bytes.as_slice()).read_to_string(&mut contents).map_err(read_error)?;
+        return Ok(contents);
+    }
+    String::from_utf8(bytes).map_err(|e| render_file_problem(path, "read-failed", &e.to_string(), style))
+}
+
+fn output_filename(input: &Path, extension: &str) -> String {
+    // "abc.asm" becomes "abc" + extension, written to the current directory. A compressed "abc.asm.gz" loses both.
+    // Only the last extension goes, so "my.prog.asm" becomes "my.prog", and a name without one is kept whole.
+    let input = match input.extension() {
+        Some(x) if x == "gz" => Path::new(input.file_stem().unwrap()),
+        _ => input,
+    };
+    let mut output_filename: String = match input.file_stem() {
+        Some(filename) => filename.to_string_lossy().into_owned(),
+        None => panic!("We tried to get the filename from user's input, but one didn't exist!"),
+    };
+    output_filename.push_str(extension);
+    output_filename
+}
+
+fn json_string(text: &str) -> String {
+    // Quote text as a JSON string, escaping the characters JSON doesn't allow as they are.
+    let mut quoted = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn render_json_error(e: &AssemblerError, file: Option<&Path>, source: &str) -> String {
+    // A one element array, so a tool can read any error output the same way. Columns are 1-based like the line,
+    // and the span is the 0-based [start, end) columns of the offending token.
+    let span = e.line().and_then(|line| source.lines().nth(line - 1)).and_then(|text| e.span(text));
+    let null = || "null".to_string();
+    let mut fields = vec![];
+    if let Some(file) = file {
+        fields.push(format!("\"file\":{}", json_string(&file.display().to_string())));
+    }
+    fields.push(format!("\"line\":{}", e.line().map_or_else(null, |x| x.to_string())));
+    fields.push(format!("\"column\":{}", span.map_or_else(null, |(start, _)| (start + 1).to_string())));
+    fields.push(format!("\"span\":{}", span.map_or_else(null, |(start, end)| format!("[{},{}]", start, end))));
+    fields.push(format!("\"code\":{}", json_string(e.code())));
+    fields.push(format!("\"message\":{}", json_string(&e.message())));
+    format!("[{{{}}}]\n", fields.join(","))
+}
+
+fn error_style(args: &Cli) -> ErrorStyle {
+    ErrorStyle {
+        color: args.color.enabled(),
+        json: args.error_format == ErrorFormat::Json,
+    }
+}
+
+fn render_file_problem(path: &Path, code: &str, problem: &str, style: ErrorStyle) -> String {
+    // A problem with a file as a whole rather than with a line of it, eg that it can't be read, in the same shape as
+    // an AssemblerError so JSON output stays parseable.
+    if style.json {
+        let fields = [
+            format!("\"file\":{}", json_string(&path.display().to_string())),
+            "\"line\":null,\"column\":null,\"span\":null".to_string(),
+            format!("\"code\":{}", json_string(code)),
+            format!("\"message\":{}", json_string(problem)),
+        ];
+        format!("[{{{}}}]\n", fields.join(","))
+    } else {
+        format!("error: {}: {}\n", path.display(), problem)
+    }
+}
+
+fn render_file_error(e: &AssemblerError, path: &Path, source: &str, style: ErrorStyle) -> String {
+    // For when there's more than one file, so the error has to say which it came from.
+    if style.json {
+        render_json_error(e, Some(path), source)
     } else {
-        if label.command_type == CommandKind::LCommand {
-            table.insert(label.symbol.to_owned(), label.line_number.to_string());
-        } else {
-            address_to_assign = last_address + 1;
-            table.insert(label.symbol, address_to_assign.to_string());
+        format!("{}: {}", path.display(), render_error(e, source, style))
+    }
+}
+
+fn render_error(e: &AssemblerError, source: &str, style: ErrorStyle) -> String {
+    // Show the error, then the offending source line with a caret under the part that's wrong, compiler style.
+    if style.json {
+        return render_json_error(e, None, source);
+    }
+    let (red, reset) = if style.color { ("\x1b[1;31m", "\x1b[0m") } else { ("", "") };
+    let mut rendered = format!("{}error{}: {}\n", red, reset, e);
+    if let Some(line) = e.line() {
+        if let Some(text) = source.lines().nth(line - 1) {
+            let gutter = " ".repeat(line.to_string().len());
+            rendered.push_str(&format!("{} |\n{} | {}\n", gutter, line, text));
+            if let Some((start, end)) = e.span(text) {
+                // Keep the source's tabs so the caret lines up however wide they are drawn.
+                let padding: String = text
+                    .chars()
+                    .take(start)
+                    .map(|x| if x == '\t' { '\t' } else { ' ' })
+                    .collect();
+                rendered.push_str(&format!("{} | {}{}{}{}\n", gutter, padding, red, "^".repeat(end - start), reset));
+            }
         }
-        (table, address_to_assign)
     }
+    rendered
 }
 
-fn write_binary_to_file(filename: String, to_write: String) -> std::io::Result<()> {
-    let mut output_file = File::create(filename)?;
-    for line in to_write.lines() {
-        let properly_formatted_line = line.to_owned() + "\n";
-        output_file.write(properly_formatted_line.as_bytes())?;
+fn read_symbol_file(path: &Path, style: ErrorStyle) -> Result<Vec<(String, u16)>, String> {
+    // A --symbols-in or --expect-symbols file. Its errors are about the file as a whole, as it isn't the program.
+    let contents =
+        std::fs::read_to_string(path).map_err(|e| render_file_problem(path, "read-failed", &e.to_string(), style))?;
+    parse_symbol_file(&contents).map_err(|e| render_file_problem(path, e.code(), &e.to_string(), style))
+}
+
+fn options(args: &Cli) -> Result<Options, String> {
+    let mut options = Options {
+        max_var_addr: args.max_var_addr,
+        max_line_len: Some(args.max_line_len),
+        jmp_shorthand: args.jmp_shorthand,
+        local_labels: args.local_labels,
+        no_predefined: args.no_predefined,
+        require_decl: args.require_decl,
+        strict: args.strict,
+        expr: args.expr,
+        warn_confusable: args.warn_confusable,
+        column_strict: args.column_strict,
+        strict_eol: args.strict_eol,
+        verify_count: args.verify_count,
+        assume_resolved: args.assume_resolved,
+        base_rom: args.base_rom,
+        c_prefix: args.c_prefix,
+        ..Options::default()
+    };
+    if let Some(symbols_path) = &args.symbols_in {
+        options.seeded_symbols = read_symbol_file(symbols_path, error_style(args))?;
     }
+    for (name, value) in &args.define {
+        options.constants.retain(|(x, _)| x != name);
+        options.constants.push((name.to_owned(), *value));
+    }
+    Ok(options)
+}
+
+fn resolved<'a>(contents: &'a str, options: &Options) -> Result<Program<'a>, AssemblerError> {
+    // Parse a program and resolve its symbols, ready for any of the outputs made from it.
+    let mut program = Program::parse(contents, options);
+    program.resolve_symbols(&mut Warnings::new(WarningLevel::None))?;
+    Ok(program)
+}
+
+fn print_counts(args: &Cli, path: &Path, style: ErrorStyle) -> Result<(), String> {
+    let contents = read_source(path, style)?;
+    let program = resolved(&contents, &options(args)?).map_err(|e| render_error(&e, &contents, style))?;
+    let counts = count(&program);
+    println!(
+        "instructions={} variables={} labels={} comments={} blank={}",
+        counts.instructions, counts.variables, counts.labels, counts.comments, counts.blank
+    );
     Ok(())
 }
 
-fn first_pass(
-    parsed_lines: Vec<ParsedLine>,
-    mut symbol_table: HashMap<Option<String>, String>,
-) -> HashMap<Option<String>, String> {
-    /* Iterate through each parsed line and populate the symbol table in two steps.
-    The two steps are needed as the first will only add LCommands, and the second does every non-numeric ACommand.
-    They cannot be done in the same loop as we do not want an ACommand that addresses a loop to be given an address
-    because that address will then be "taken", but the LCommand will overwrite the assigned address.
-    EG we don't want to give @LOOP an address as (LOOP) will overwrite it, and no other ACommand will get that address.
-    */
+fn print_metrics(args: &Cli, path: &Path, style: ErrorStyle) -> Result<(), String> {
+    let contents = read_source(path, style)?;
+    let program = resolved(&contents, &options(args)?).map_err(|e| render_error(&e, &contents, style))?;
+    let metrics = jump_metrics(&program);
+    println!(
+        "jumps={} conditional={} unconditional={} targets={} branches={}",
+        metrics.jumps, metrics.conditional, metrics.unconditional, metrics.targets, metrics.branches
+    );
+    Ok(())
+}
+
+fn print_normalized(args: &Cli, path: &Path, style: ErrorStyle) -> Result<(), String> {
+    let contents = read_source(path, style)?;
+    let normalized = normalize(&contents, &options(args)?).map_err(|e| render_error(&e, &contents, style))?;
+    print!("{}", normalized);
+    Ok(())
+}
+
+fn print_ast(args: &Cli, path: &Path, style: ErrorStyle) -> Result<(), String> {
+    let contents = read_source(path, style)?;
+    print!("{}", dump_ast(&contents, &options(args)?));
+    Ok(())
+}
+
+fn print_from_annotated(path: &Path, style: ErrorStyle) -> Result<(), String> {
+    let contents = read_source(path, style)?;
+    let source = from_annotated(&contents).map_err(|e| render_error(&e, &contents, style))?;
+    print!("{}", source);
+    Ok(())
+}
+
+fn print_explanation(args: &Cli, path: &Path, line: usize, style: ErrorStyle) -> Result<(), String> {
+    let contents = read_source(path, style)?;
+    let explained = resolved(&contents, &options(args)?)
+        .and_then(|program| explain_line(&program, line))
+        .map_err(|e| render_error(&e, &contents, style))?;
+    print!("{}", explained);
+    Ok(())
+}
+
+fn print_diff(args: &Cli, a: &Path, b: &Path, style: ErrorStyle) -> Result<bool, String> {
+    // Returns whether the two programs assemble to the same machine code.
+    let options = options(args)?;
+    let mut assembled = vec![];
+    for path in &[a, b] {
+        let contents = read_source(path, style)?;
+        let words = assemble_words(&contents, &options, &mut Warnings::new(WarningLevel::None))
+            .map_err(|e| render_file_error(&e, path, &contents, style))?;
+        assembled.push(words);
+    }
+    let differences = diff_words(&assembled[0], &assembled[1]);
+    let word = |x: Option<u16>| x.map_or("-".repeat(16), |x| format!("{:016b}", x));
+    for (index, a_word, b_word) in &differences {
+        println!("{:5}: {} {}", index + options.base_rom as usize, word(*a_word), word(*b_word));
+    }
+    if assembled[0].len() != assembled[1].len() {
+        println!("{} is {} words, {} is {}", a.display(), assembled[0].len(), b.display(), assembled[1].len());
+    }
+    Ok(differences.is_empty())
+}
+
+fn build(args: &Cli, path: &Path, style: ErrorStyle) -> Result<(), String> {
+    // Assemble the program once, writing each of the files asked for. Problems come back as the text to print rather
+    // than exiting, so --watch can report them and keep going.
+    if path.extension().is_some_and(|x| x == "hack") {
+        // Assembler output passed back in by mistake. Its words read as C-instructions that don't exist, which
+        // gives a baffling error about the first line rather than saying what's actually wrong.
+        let problem = "this looks like assembler output, not source";
+        if args.strict {
+            return Err(render_file_problem(path, "assembler-output", problem, style));
+        }
+        if args.warning_level > WarningLevel::None {
+            log::warn!("{}: {}", path.display(), problem);
+        }
+    }
+    let wants_listing = args.listing || args.emit.contains(&Artifact::Listing);
+    if args.recover && !wants_listing {
+        return Err(render_file_problem(path, "invalid-option", "--recover needs --listing or --emit listing", style));
+    }
+    let contents = read_source(path, style)?;
+    let options = options(args)?;
+    // JSON errors always name the file, so a tool reading the errors from --dir can tell which each belongs to.
+    let render = |e: AssemblerError| match style.json {
+        true => render_file_error(&e, path, &contents, style),
+        false => render_error(&e, &contents, style),
+    };
 
-    // This is the first loop, where we only populate (XXX) symbols into the table
-    let mut last_ram_address: isize = 15;
-    for parsed_line in &parsed_lines {
-        if parsed_line.command_type != CommandKind::ICommand {
-            if parsed_line.to_owned().command_type == CommandKind::LCommand {
-                let (symbol_table_destr, last_addr) =
-                    populate_symbol_table(parsed_line.to_owned(), symbol_table, last_ram_address);
-                symbol_table = symbol_table_destr;
-                last_ram_address = last_addr;
+    // The program is parsed and its symbols resolved once, and every file below is made from that. Warnings are
+    // reported through the logger as they are found.
+    let mut warnings = Warnings::new(args.warning_level);
+    let mut program = Program::parse(&contents, &options);
+    program.resolve_symbols(&mut warnings).map_err(render)?;
+    let header = match args.header {
+        true => build_header(path, &program),
+        false => String::new(),
+    };
+    if wants_listing {
+        // With --recover the listing is written even when instructions fail to encode, before that fails the build.
+        let listed = listing(&program, args.recover).map_err(render)?;
+        let listed = header.to_owned() + &listed;
+        let bytes = output_bytes(&listed, !args.no_trailing_newline);
+        write_output(Path::new(&output_filename(path, ".lst")), &bytes, style)?;
+    }
+
+    let assembled = program.to_hack_text();
+    if args.lint && assembled.is_ok() {
+        lint(&program, &mut warnings);
+    }
+    let mut translated_contents = assembled.map_err(render)?;
+    if let RomSize::Words(size) = args.rom_size {
+        translated_contents = pad_rom(&translated_contents, size, &options).map_err(render)?;
+    }
+    if let Some(image_path) = &args.dump_rom_image {
+        let image: Vec<u8> = to_words(&translated_contents)
+            .iter()
+            .flat_map(|word| match args.endian {
+                Endian::Big => word.to_be_bytes(),
+                Endian::Little => word.to_le_bytes(),
+            })
+            .collect();
+        write_output(image_path, &image, style)?;
+    }
+    if let Some(bank_size) = args.bank_size.filter(|_| args.emit.contains(&Artifact::Bin)) {
+        write_banks(args, path, &translated_contents, bank_size, &options, style)?;
+    }
+    if args.emit.contains(&Artifact::Bin) && args.bank_size.is_none() {
+        let (extension, output) = match args.format {
+            Format::Binary if args.annotate => {
+                // One line per word, so it pads the same way the plain words did above.
+                let mut annotated = annotate(&program)
+                    .map_err(render)?;
+                if let RomSize::Words(size) = args.rom_size {
+                    annotated = pad_annotated(&annotated, size, &options).map_err(render)?;
+                }
+                (".hack", header + &annotated)
+            }
+            Format::Binary => (".hack", translated_contents),
+            Format::Hex => (".hex", to_hex(&translated_contents)),
+            Format::CArray => (".h", to_c_array(&translated_contents, &args.array_name)),
+            Format::Hexdump => (
+                ".dump",
+                hexdump(&program)
+                    .map_err(render)?,
+            ),
+            Format::Csv => (
+                ".csv",
+                header
+                    + &csv(&program)
+                        .map_err(render)?,
+            ),
+        };
+        let filename = output_filename(path, extension);
+        let bytes = output_bytes(&output, !args.no_trailing_newline);
+        write_output(Path::new(&filename), &bytes, style)?;
+        if args.clipboard {
+            copy_to_clipboard(&output).map_err(|e| {
+                render_file_problem(Path::new(&filename), "clipboard-failed", &format!("can't copy: {}", e), style)
+            })?;
+        }
+        if args.checksum || args.checksum_file.is_some() {
+            // In the "checksum  filename" layout of the sha256sum family, so graders can compare it as text.
+            let line = format!("{:08x}  {}", crc32fast::hash(&bytes), filename);
+            if args.checksum {
+                eprintln!("crc32: {}", line);
+            }
+            if let Some(checksum_path) = &args.checksum_file {
+                write_output(checksum_path, (line + "\n").as_bytes(), style)?;
             }
         }
     }
+    if args.emit.contains(&Artifact::Sym) {
+        let symbols = format_symbol_file(program.symbols());
+        let bytes = output_bytes(&symbols, !args.no_trailing_newline);
+        write_output(Path::new(&output_filename(path, ".sym")), &bytes, style)?;
+    }
+    if let Some(expected_path) = &args.expect_symbols {
+        let expected = read_symbol_file(expected_path, style)?;
+        let mismatches: Vec<String> = symbol_mismatches(program.symbols(), &expected)
+            .into_iter()
+            .map(|(name, address, actual)| match actual {
+                Some(actual) => format!("{}: expected {}, found {}", name, address, actual),
+                None => format!("{}: expected {}, not defined", name, address),
+            })
+            .collect();
+        if !mismatches.is_empty() {
+            if style.json {
+                let problem = format!("symbols don't match: {}", mismatches.join("; "));
+                return Err(render_file_problem(expected_path, "symbol-mismatch", &problem, style));
+            }
+            let mut rendered = format!("error: symbols don't match {}\n", expected_path.display());
+            for mismatch in mismatches {
+                rendered += &format!("  {}\n", mismatch);
+            }
+            return Err(rendered);
+        }
+    }
+    Ok(())
+}
 
-    // Handle A commands that are not an address
-    for parsed_line in &parsed_lines {
-        if parsed_line.command_type != CommandKind::ICommand {
-            if parsed_line.to_owned().command_type == CommandKind::ACommand
-                && !parsed_line
-                    .to_owned()
-                    .symbol
-                    .unwrap()
-                    .chars()
-                    .nth(0)
-                    .unwrap()
-                    .is_numeric()
-            {
-                let (symbol_table_a, loop_lines_b) =
-                    populate_symbol_table(parsed_line.to_owned(), symbol_table, last_ram_address);
-                symbol_table = symbol_table_a;
-                last_ram_address = loop_lines_b;
+fn write_banks(
+    args: &Cli,
+    path: &Path,
+    translated_contents: &str,
+    bank_size: usize,
+    options: &Options,
+    style: ErrorStyle,
+) -> Result<(), String> {
+    // Bank n holds the words from n * bank_size on. A program with no instructions has no banks.
+    let extension = match args.format {
+        Format::Binary => "hack",
+        Format::Hex => "hex",
+        Format::Hexdump | Format::Csv | Format::CArray => {
+            let problem = "--bank-size can only split binary or hex output";
+            return Err(render_file_problem(path, "invalid-option", problem, style));
+        }
+    };
+    if bank_size == 0 {
+        return Err(render_file_problem(path, "invalid-option", "--bank-size must be at least 1", style));
+    }
+    // Filled the same way as any other unused ROM, so it follows --c-prefix.
+    let fill = format!("{:016b}", fill_word(options));
+    let words: Vec<&str> = translated_contents.lines().collect();
+    for (bank, chunk) in words.chunks(bank_size).enumerate() {
+        let mut bank_words = chunk.to_vec();
+        if args.pad_banks {
+            bank_words.resize(bank_size, &fill);
+        }
+        let mut output = bank_words.join("\n");
+        if let Format::Hex = args.format {
+            output = to_hex(&output);
+        }
+        let filename = output_filename(path, &format!(".{}.{}", bank, extension));
+        write_output(Path::new(&filename), &output_bytes(&output, !args.no_trailing_newline), style)?;
+    }
+    Ok(())
+}
+
+fn build_header(path: &Path, program: &Program) -> String {
+    // The header is made once the program's symbols are resolved, before anything is translated.
+    let built = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|x| x.parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_secs());
+    let mut header = format!(
+        "// {} {}\n// source: {}\n// built: {}\n",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        path.display(),
+        utc_timestamp(built)
+    );
+    header.push_str(&format!("// instructions: {}\n", count(program).instructions));
+    header
+}
+
+fn utc_timestamp(seconds: u64) -> String {
+    // Seconds since the Unix epoch as an ISO 8601 UTC time, eg 2024-03-01T12:00:00Z. The date is worked out with
+    // Howard Hinnant's days_from_civil algorithm run backwards, in eras of 400 years that all have 146097 days.
+    let (days, time) = (seconds / 86400, seconds % 86400);
+    let z = days + 719468;
+    let (era, day_of_era) = (z / 146097, z % 146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153; // Counting from March, so the leap day falls at the end.
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+fn asm_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    // The .asm files in the directory (not its subdirectories), in name order.
+    let mut sources = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_file() && path.extension().is_some_and(|x| x == "asm") {
+            sources.push(path);
+        }
+    }
+    sources.sort();
+    Ok(sources)
+}
+
+fn build_dir(args: &Cli, dir: &Path, style: ErrorStyle) -> std::io::Result<bool> {
+    // Assemble each .asm file in the directory, carrying on past failures, and finish with a summary. Returns whether
+    // every file assembled.
+    let sources = asm_files(dir)?;
+    let mut failed = 0;
+    for path in &sources {
+        // With JSON errors, only the errors are written, as they already say which file each came from.
+        match build(args, path, style) {
+            Ok(()) if args.quiet || style.json => (),
+            Ok(()) => eprintln!("{}: ok", path.display()),
+            Err(rendered) if style.json => {
+                failed += 1;
+                eprint!("{}", rendered);
+            }
+            Err(rendered) => {
+                failed += 1;
+                eprint!("{}: {}", path.display(), rendered);
             }
         }
     }
-    symbol_table
-}
-
-fn second_pass(parsed_lines: Vec<ParsedLine>, symbol_table: HashMap<Option<String>, String>) -> String {
-    // Do the second pass of translating the lines
-    // TODO: Take the parsed_contents from pass 1 and iterate through the collection of them to avoid parsing each line
-    // for a second time here.
-    let mut translated_contents = String::new();
-    for parsed_line in parsed_lines {
-        if parsed_line.command_type != CommandKind::ICommand
-            && parsed_line.command_type != CommandKind::LCommand
-        {
-            if translated_contents.is_empty() {
-                translated_contents =
-                    translated_contents + translate(parsed_line, symbol_table.to_owned()).as_str();
-            } else {
-                translated_contents = translated_contents
-                    + "\n"
-                    + translate(parsed_line, symbol_table.to_owned()).as_str();
+    if !args.quiet && !style.json {
+        eprintln!("{} assembled, {} failed", sources.len() - failed, failed);
+    }
+    Ok(failed == 0)
+}
+
+fn write_bundle(args: &Cli, sources: &[PathBuf], bundle: &Path, style: ErrorStyle) -> Result<(), String> {
+    // The archive is built in memory and only written once every file has assembled, so a failure leaves nothing.
+    let options = options(args)?;
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+    let zip_error = |e: zip::result::ZipError| render_file_problem(bundle, "write-failed", &e.to_string(), style);
+    for path in sources {
+        let contents = read_source(path, style)?;
+        let assembled = assemble_with(&contents, &options, &mut Warnings::new(args.warning_level))
+            .map_err(|e| render_file_error(&e, path, &contents, style))?;
+        zip.start_file(output_filename(path, ".hack"), zip::write::SimpleFileOptions::default())
+            .map_err(zip_error)?;
+        zip.write_all(&output_bytes(&assembled, !args.no_trailing_newline))
+            .map_err(|e| render_file_problem(bundle, "write-failed", &e.to_string(), style))?;
+    }
+    let archive = zip.finish().map_err(zip_error)?.into_inner();
+    write_output(bundle, &archive, style)
+}
+
+fn build_linked(args: &Cli, paths: &[PathBuf], style: ErrorStyle) -> Result<(), String> {
+    let mut sources = vec![];
+    for path in paths {
+        sources.push(read_source(path, style)?);
+    }
+    let names: Vec<String> = paths.iter().map(|x| x.display().to_string()).collect();
+    let programs: Vec<(&str, &str)> = names.iter().zip(&sources).map(|(x, y)| (x.as_str(), y.as_str())).collect();
+    let linked = link(&programs, &options(args)?, &mut Warnings::new(args.warning_level))
+        .map_err(|(program, e)| render_file_error(&e, &paths[program], &sources[program], style))?;
+    let filename = output_filename(&paths[0], ".hack");
+    write_output(Path::new(&filename), &output_bytes(&linked, !args.no_trailing_newline), style)
+}
+
+fn repl(args: &Cli) -> Result<(), String> {
+    // Everything accepted so far is kept as a program, and its symbols are resolved afresh for each new line, so they
+    // are allocated exactly as assembling the lines as a file would: labels at the ROM address of the next
+    // instruction, variables from 16 up around the seeded symbols, and --require-decl and --max-var-addr applied. The
+    // whole program is encoded too, with the same options, and only the words the new line added are printed.
+    let options = options(args)?;
+    let resolve = |program: &str| resolve_symbols(program, &options, &mut Warnings::new(WarningLevel::None));
+    resolve("").map_err(|e| format!("error: {}\n", e))?;
+    let mut program = String::new();
+    let mut emitted = 0;
+    let interactive = atty::is(atty::Stream::Stdin);
+    let mut input = String::new();
+    loop {
+        if interactive {
+            print!("> ");
+            std::io::stdout().flush().map_err(|e| format!("error: {}\n", e))?;
+        }
+        input.clear();
+        if std::io::stdin().read_line(&mut input).map_err(|e| format!("error: {}\n", e))? == 0 {
+            return Ok(());
+        }
+        let instruction = match preprocess(&input).pop().and_then(|x| x.instruction) {
+            Some(instruction) => instruction,
+            None => continue,
+        };
+        if instruction == ".quit" {
+            return Ok(());
+        } else if instruction == ".symbols" {
+            print!("{}", format_symbol_file(&resolve(&program).map_err(|e| format!("error: {}\n", e))?));
+            continue;
+        }
+        // A line that fails isn't kept, so it leaves no symbols behind.
+        let extended = format!("{}{}\n", program, instruction);
+        let mut parsed = Program::parse(&extended, &options);
+        let encoded = parsed.resolve_symbols(&mut Warnings::new(WarningLevel::None)).and_then(|_| parsed.encode());
+        match encoded {
+            Ok(words) => {
+                for word in &words[emitted..] {
+                    println!("{:016b}", word);
+                }
+                emitted = words.len();
+                program = extended;
             }
+            Err(e) => println!("error: {}", e.message()),
         }
     }
-    translated_contents
 }
 
-fn main() {
-    let args = Cli::from_args();
-    let contents = get_file_contents(&args.path);
-    let parsed_lines = parse_each_line(contents.to_owned());
+fn watched_path(path: &Path) -> std::io::Result<PathBuf> {
+    // The full path an input's events will name, eg "abc.asm" in the current directory is "/home/me/abc.asm".
+    let directory = match path.parent() {
+        Some(x) if !x.as_os_str().is_empty() => x,
+        _ => Path::new("."),
+    };
+    let filename = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "the path has no file name"))?;
+    Ok(std::fs::canonicalize(directory)?.join(filename))
+}
 
-    let mut output_filename: String = match args.path.file_stem() {
-        Some(filename) => String::from(filename.to_str().unwrap()),
-        None => panic!("We tried to get the filename from user's input, but one didn't exist!"),
+fn start_watching(
+    inputs: &[&Path],
+    sender: mpsc::Sender<notify::Result<notify::Event>>,
+) -> Result<(Vec<PathBuf>, impl Watcher), String> {
+    // The inputs' full paths, and the watcher, which stops sending events once it is dropped.
+    let watched = inputs.iter().map(|x| watched_path(x)).collect::<std::io::Result<Vec<PathBuf>>>();
+    let watched = watched.map_err(|e| e.to_string())?;
+    let mut watcher = notify::recommended_watcher(sender).map_err(|e| e.to_string())?;
+    for input in &watched {
+        watcher.watch(input.parent().unwrap(), RecursiveMode::NonRecursive).map_err(|e| e.to_string())?;
+    }
+    Ok((watched, watcher))
+}
+
+fn watch(args: &Cli, path: &Path, style: ErrorStyle) -> ! {
+    /* The directories the inputs are in are watched rather than the inputs themselves, as many editors save by
+        writing a new file and renaming it over the old one, which a watch on the old file never sees. After a change,
+        wait until the inputs stop changing for a moment so that an editor's burst of writes for one save only
+        triggers one reassembly. Changes to anything else in the directories, eg the output files, are ignored.
+    */
+    const SETTLE: Duration = Duration::from_millis(300);
+    let mut inputs = vec![path];
+    inputs.extend(args.symbols_in.as_deref());
+    let (sender, receiver) = mpsc::channel();
+    let (watched, _watcher) = start_watching(&inputs, sender).unwrap_or_else(|e| {
+        eprint!("{}", render_file_problem(path, "watch-failed", &format!("can't watch: {}", e), style));
+        std::process::exit(1);
+    });
+    // Only a change counts, not the assembler reading the input again. An error from the watcher might have hidden a
+    // change, so it counts as one.
+    let changed = |event: &notify::Result<notify::Event>| {
+        event.as_ref().map_or(true, |x| !x.kind.is_access() && x.paths.iter().any(|path| watched.contains(path)))
     };
-    output_filename.push_str(".hack");
-
-    // Set the pre-set symbols into the table
-    let mut symbol_table: HashMap<Option<String>, String> = [
-        (Some(String::from("SP")), String::from("0")),
-        (Some(String::from("R0")), String::from("0")),
-        (Some(String::from("LCL")), String::from("1")),
-        (Some(String::from("R1")), String::from("1")),
-        (Some(String::from("ARG")), String::from("2")),
-        (Some(String::from("R2")), String::from("2")),
-        (Some(String::from("THIS")), String::from("3")),
-        (Some(String::from("R3")), String::from("3")),
-        (Some(String::from("THAT")), String::from("4")),
-        (Some(String::from("R4")), String::from("4")),
-        (Some(String::from("R5")), String::from("5")),
-        (Some(String::from("R6")), String::from("6")),
-        (Some(String::from("R7")), String::from("7")),
-        (Some(String::from("R8")), String::from("8")),
-        (Some(String::from("R9")), String::from("9")),
-        (Some(String::from("R10")), String::from("10")),
-        (Some(String::from("R11")), String::from("11")),
-        (Some(String::from("R12")), String::from("12")),
-        (Some(String::from("R13")), String::from("13")),
-        (Some(String::from("R14")), String::from("14")),
-        (Some(String::from("R15")), String::from("15")),
-        (Some(String::from("SCREEN")), String::from("16384")),
-        (Some(String::from("KBD")), String::from("24576")),
-    ]
-    .iter()
-    .cloned()
-    .collect();
-    symbol_table = first_pass(parsed_lines.to_owned(), symbol_table.to_owned());
-
-    let translated_contents = second_pass(parsed_lines.to_owned(), symbol_table.to_owned()).to_string();
-
-    match write_binary_to_file(output_filename, translated_contents.to_owned()) {
-        Ok(_) => (),
-        Err(e) => panic!("Failed to write output to file: {}", e),
+    loop {
+        match build(args, path, style) {
+            Ok(()) => eprintln!("[{}] ok", path.display()),
+            Err(rendered) => eprint!("{}", rendered),
+        }
+        eprintln!("[{}] watching for changes...", path.display());
+        // The watcher holds the sending end for as long as this runs, so this only finishes on a change.
+        receiver.iter().find(|event| changed(event));
+        let mut settled = Instant::now() + SETTLE;
+        while let Ok(event) = receiver.recv_timeout(settled.saturating_duration_since(Instant::now())) {
+            if changed(&event) {
+                settled = Instant::now() + SETTLE;
+            }
+        }
+    }
+}
+
+fn init_logging(verbose: u8, quiet: bool) {
+    // Log lines look like the assembler's other diagnostics, eg "warning: line 3: ...".
+    let level = match verbose {
+        _ if quiet => log::LevelFilter::Error,
+        0 => log::LevelFilter::Warn,
+        1 => log::LevelFilter::Info,
+        2 => log::LevelFilter::Debug,
+        _ => log::LevelFilter::Trace,
     };
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(level);
+    if !quiet {
+        builder.parse_env("RUST_LOG");
+    }
+    builder
+        .format(|buf, record| {
+            let level = match record.level() {
+                log::Level::Warn => "warning".to_string(),
+                level => level.to_string().to_lowercase(),
+            };
+            writeln!(buf, "{}: {}", level, record.args())
+        })
+        .init();
+}
+
+fn main() {
+    let args = Cli::from_args();
+    init_logging(args.verbose, args.quiet);
+    let style = error_style(&args);
+    if let Some(Command::SelfTest { fixtures }) = &args.command {
+        let results = match fixtures {
+            Some(dir) => check_fixture_dir(dir).unwrap_or_else(|e| {
+                eprint!("{}", render_file_problem(dir, "read-failed", &e.to_string(), style));
+                std::process::exit(1);
+            }),
+            None => self_test(),
+        };
+        let width = results.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        for (name, result) in &results {
+            match result {
+                Ok(()) => println!("{:<width$} ok", name, width = width),
+                Err(e) => println!("{:<width$} FAILED: {}", name, e, width = width),
+            }
+        }
+        std::process::exit(if results.iter().all(|(_, result)| result.is_ok()) { 0 } else { 1 });
+    }
+    if let Some(Command::Repl) = &args.command {
+        if let Err(rendered) = repl(&args) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Diff { a, b }) = &args.command {
+        match print_diff(&args, a, b, style) {
+            Ok(same) => std::process::exit(if same { 0 } else { 1 }),
+            Err(rendered) => {
+                eprint!("{}", rendered);
+                std::process::exit(2);
+            }
+        }
+    }
+    if args.list_comps {
+        print!("{}", opcode_tables());
+        return;
+    }
+    if args.memory_map {
+        print!("{}", memory_map());
+        return;
+    }
+    if let Some(bundle) = &args.bundle {
+        let sources = match &args.dir {
+            Some(dir) => asm_files(dir).unwrap_or_else(|e| {
+                eprint!("{}", render_file_problem(dir, "read-failed", &e.to_string(), style));
+                std::process::exit(1);
+            }),
+            None => vec![args.path.clone().unwrap()],
+        };
+        if let Err(rendered) = write_bundle(&args, &sources, bundle, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if !args.link.is_empty() {
+        if let Err(rendered) = build_linked(&args, &args.link, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(dir) = &args.dir {
+        match build_dir(&args, dir, style) {
+            Ok(all_assembled) => std::process::exit(if all_assembled { 0 } else { 1 }),
+            Err(e) => {
+                eprint!("{}", render_file_problem(dir, "read-failed", &e.to_string(), style));
+                std::process::exit(1);
+            }
+        }
+    }
+    let path = args.path.clone().unwrap();
+    if args.count_only {
+        if let Err(rendered) = print_counts(&args, &path, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.metrics {
+        if let Err(rendered) = print_metrics(&args, &path, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.normalize_only {
+        if let Err(rendered) = print_normalized(&args, &path, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.from_annotated {
+        if let Err(rendered) = print_from_annotated(&path, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(line) = args.explain_line {
+        if let Err(rendered) = print_explanation(&args, &path, line, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.dump_ast {
+        if let Err(rendered) = print_ast(&args, &path, style) {
+            eprint!("{}", rendered);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if args.watch {
+        watch(&args, &path, style);
+    }
+    if let Err(rendered) = build(&args, &path, style) {
+        eprint!("{}", rendered);
+        std::process::exit(1);
+    }
 }
@@ -0,0 +1,90 @@
+/* A quick check that a build of the assembler works: assemble the small programs from the course that are embedded in
+    the binary and compare them byte for byte against their known-good .hack output. The same check can be run over a
+    directory of fixtures, where every "abc.asm" is compared against the "abc.hack" beside it.
+*/
+use crate::{assemble, write_binary};
+use std::{io, path::Path};
+
+// Name, source, and expected output of each embedded program. Comps and Zero aren't from the course. Comps goes
+// through every comp with and without a dest and a jump, to catch parser regressions, and Zero checks a label at
+// address 0 is kept apart from the literal @0.
+const PROGRAMS: [(&str, &str, &str); 5] = [
+    (
+        "Add",
+        include_str!("../tests/fixtures/Add.asm"),
+        include_str!("../tests/fixtures/Add.hack"),
+    ),
+    (
+        "Comps",
+        include_str!("../tests/fixtures/Comps.asm"),
+        include_str!("../tests/fixtures/Comps.hack"),
+    ),
+    (
+        "Max",
+        include_str!("../tests/fixtures/Max.asm"),
+        include_str!("../tests/fixtures/Max.hack"),
+    ),
+    (
+        "Rect",
+        include_str!("../tests/fixtures/Rect.asm"),
+        include_str!("../tests/fixtures/Rect.hack"),
+    ),
+    (
+        "Zero",
+        include_str!("../tests/fixtures/Zero.asm"),
+        include_str!("../tests/fixtures/Zero.hack"),
+    ),
+];
+
+/// Assemble `source` and compare the `.hack` output with `expected`, describing the first difference if any.
+pub fn check_fixture(source: &str, expected: &str) -> Result<(), String> {
+    let text = assemble(source).map_err(|e| format!("failed to assemble: {}", e))?;
+    let mut output = vec![];
+    write_binary(&mut output, &text).map_err(|e| e.to_string())?;
+    if output == expected.as_bytes() {
+        return Ok(());
+    }
+    let actual = String::from_utf8_lossy(&output);
+    for (index, (got, wanted)) in actual.lines().zip(expected.lines()).enumerate() {
+        if got != wanted {
+            return Err(format!("word {}: expected {}, got {}", index, wanted, got));
+        }
+    }
+    Err(format!(
+        "expected {} words, got {}",
+        expected.lines().count(),
+        actual.lines().count()
+    ))
+}
+
+/// Check every embedded program, returning each one's name and result.
+pub fn self_test() -> Vec<(String, Result<(), String>)> {
+    PROGRAMS
+        .iter()
+        .map(|(name, source, expected)| (name.to_string(), check_fixture(source, expected)))
+        .collect()
+}
+
+/// Check every `.asm` file in `dir` against the `.hack` file of the same name, in name order. A program without a
+/// `.hack` file fails, so a fixture can't go unchecked.
+pub fn check_fixture_dir(dir: &Path) -> io::Result<Vec<(String, Result<(), String>)>> {
+    let mut sources = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "asm") {
+            sources.push(path);
+        }
+    }
+    sources.sort();
+    let mut results = vec![];
+    for path in sources {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = std::fs::read_to_string(&path)?;
+        let result = match std::fs::read_to_string(path.with_extension("hack")) {
+            Ok(expected) => check_fixture(&source, &expected),
+            Err(e) => Err(format!("no expected output: {}", e)),
+        };
+        results.push((name, result));
+    }
+    Ok(results)
+}
@@ -0,0 +1,113 @@
+/* Warnings are problems that don't stop a program from assembling but are probably mistakes. They are gathered in a
+    Warnings collector while assembling instead of being printed on the spot, so the caller decides how (and whether)
+    to show them. Each kind of warning belongs to a level; only kinds at or below the collector's level are kept.
+    Kept warnings are also logged with log::warn!, which does nothing unless the caller has installed a logger. A
+    quiet collector skips the logging, for warnings that are adjusted and reported again before anyone sees them.
+*/
+use std::{fmt, str::FromStr};
+
+/// How many warnings to report, from nothing at all to every category.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum WarningLevel {
+    None,
+    #[default]
+    Default,
+    All,
+}
+
+impl FromStr for WarningLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "none" => Ok(WarningLevel::None),
+            "default" => Ok(WarningLevel::Default),
+            "all" => Ok(WarningLevel::All),
+            _ => Err(format!("unknown warning level '{}', expected none, default, or all", level)),
+        }
+    }
+}
+
+/// The categories of warning the assembler can emit.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum WarningKind {
+    Shadowing,     // A label redefines a predefined symbol or an earlier label.
+    ScreenOverlap, // A variable was allocated inside the SCREEN/KBD memory map.
+    DanglingLabel, // A label points past the last instruction.
+    Unreachable,   // An instruction follows an unconditional jump with no label in between. Only found by lint.
+    UnusedLoad,    // An A-instruction's value is replaced before anything reads it. Only found by lint.
+    MemoryMap,     // M is used in a way the memory map doesn't allow, eg storing to KBD. Only found by lint.
+    UnusedLabel,   // A label no A-instruction refers to. Only found by lint.
+    VariableUse,   // A variable is only ever written, or only ever read. Only found by lint.
+    Confusable,    // A symbol is named like a register or mnemonic, eg @D. Only looked for with warn_confusable.
+}
+
+impl WarningKind {
+    /// The lowest level at which this kind of warning is reported.
+    pub fn level(self) -> WarningLevel {
+        match self {
+            WarningKind::Shadowing
+            | WarningKind::ScreenOverlap
+            | WarningKind::Unreachable
+            | WarningKind::UnusedLoad
+            | WarningKind::MemoryMap
+            | WarningKind::UnusedLabel
+            | WarningKind::VariableUse
+            | WarningKind::Confusable => WarningLevel::Default,
+            WarningKind::DanglingLabel => WarningLevel::All,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Collects the warnings enabled at its level while a program is assembled.
+#[derive(Debug, Default)]
+pub struct Warnings {
+    level: WarningLevel,
+    collected: Vec<Warning>,
+    quiet: bool,
+}
+
+impl Warnings {
+    pub fn new(level: WarningLevel) -> Warnings {
+        Warnings {
+            level,
+            collected: vec![],
+            quiet: false,
+        }
+    }
+
+    pub(crate) fn quiet(level: WarningLevel) -> Warnings {
+        Warnings {
+            quiet: true,
+            ..Warnings::new(level)
+        }
+    }
+
+    pub(crate) fn warn(&mut self, kind: WarningKind, line: usize, message: String) {
+        if kind.level() <= self.level {
+            let warning = Warning { kind, line, message };
+            if !self.quiet {
+                log::warn!("{}", warning);
+            }
+            self.collected.push(warning);
+        }
+    }
+
+    /// The warnings collected so far, in the order they were found.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.collected
+    }
+}
@@ -0,0 +1,9 @@
+// This file is part of www.nand2tetris.org
+// Computes R0 = 2 + 3  (R0 refers to RAM[0])
+
+@2
+D=A
+@3
+D=D+A
+@0
+M=D
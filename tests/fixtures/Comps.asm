@@ -0,0 +1,119 @@
+// Every comp, with and without a dest and a jump.
+// The expected words are built from the comp, dest and jump bit tables.
+
+0
+0;JMP
+D=0
+D=0;JMP
+1
+1;JMP
+D=1
+D=1;JMP
+-1
+-1;JMP
+D=-1
+D=-1;JMP
+D
+D;JMP
+D=D
+D=D;JMP
+A
+A;JMP
+D=A
+D=A;JMP
+M
+M;JMP
+D=M
+D=M;JMP
+!D
+!D;JMP
+D=!D
+D=!D;JMP
+!A
+!A;JMP
+D=!A
+D=!A;JMP
+!M
+!M;JMP
+D=!M
+D=!M;JMP
+-D
+-D;JMP
+D=-D
+D=-D;JMP
+-A
+-A;JMP
+D=-A
+D=-A;JMP
+-M
+-M;JMP
+D=-M
+D=-M;JMP
+D+1
+D+1;JMP
+D=D+1
+D=D+1;JMP
+A+1
+A+1;JMP
+D=A+1
+D=A+1;JMP
+M+1
+M+1;JMP
+D=M+1
+D=M+1;JMP
+D-1
+D-1;JMP
+D=D-1
+D=D-1;JMP
+A-1
+A-1;JMP
+D=A-1
+D=A-1;JMP
+M-1
+M-1;JMP
+D=M-1
+D=M-1;JMP
+D+A
+D+A;JMP
+D=D+A
+D=D+A;JMP
+D+M
+D+M;JMP
+D=D+M
+D=D+M;JMP
+D-A
+D-A;JMP
+D=D-A
+D=D-A;JMP
+D-M
+D-M;JMP
+D=D-M
+D=D-M;JMP
+A-D
+A-D;JMP
+D=A-D
+D=A-D;JMP
+M-D
+M-D;JMP
+D=M-D
+D=M-D;JMP
+D&A
+D&A;JMP
+D=D&A
+D=D&A;JMP
+D&M
+D&M;JMP
+D=D&M
+D=D&M;JMP
+D|A
+D|A;JMP
+D=D|A
+D=D|A;JMP
+D|M
+D|M;JMP
+D=D|M
+D=D|M;JMP
+M=D&A
+AM=D&M;JNE
+D=D|M;JEQ
+AMD=D|A;JLE
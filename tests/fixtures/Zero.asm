@@ -0,0 +1,7 @@
+// A label at ROM address 0 next to the literal address 0.
+// @0 is the number itself, whatever labels exist, while @ZERO is looked up and also comes to 0.
+(ZERO)
+@0
+D=A
+@ZERO
+0;JMP